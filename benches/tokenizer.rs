@@ -1,5 +1,11 @@
 use criterion::{criterion_group, criterion_main, Criterion};
+use gosub_engine::bytes::CharIterator;
+use gosub_engine::html5::error_logger::ErrorLogger;
+use gosub_engine::html5::tokenizer::token::Token;
+use gosub_engine::html5::tokenizer::{ParserData, Tokenizer};
 use gosub_engine::testing::tokenizer::{self, FixtureFile};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 fn criterion_benchmark(c: &mut Criterion) {
     // Criterion can report inconsistent results from run to run in some cases.  We attempt to
@@ -26,6 +32,47 @@ fn criterion_benchmark(c: &mut Criterion) {
         })
     });
 
+    // A single large text node, which grows the consume buffer unless it has been pre-sized
+    let large_text = "lorem ipsum dolor sit amet ".repeat(40_000);
+
+    for (name, capacity) in [
+        ("large_text", None),
+        ("large_text_with_capacity", Some(1 << 20)),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut chars = CharIterator::new();
+                chars.read_from_str(&large_text, None);
+
+                let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+                let mut tokenizer = match capacity {
+                    Some(capacity) => {
+                        Tokenizer::with_capacity(&mut chars, None, error_logger, capacity)
+                    }
+                    None => Tokenizer::new(&mut chars, None, error_logger),
+                };
+
+                while tokenizer.next_token(ParserData::default()).unwrap() != Token::Eof {}
+            })
+        });
+    }
+
+    // Text that consists mostly of named character references
+    let entity_text =
+        "&amp; &lt;&gt; &copy &notin; &CounterClockwiseContourIntegral; x&y ".repeat(2_000);
+
+    group.bench_function("entity_heavy", |b| {
+        b.iter(|| {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(&entity_text, None);
+
+            let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+            let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger);
+
+            while tokenizer.next_token(ParserData::default()).unwrap() != Token::Eof {}
+        })
+    });
+
     group.finish();
 }
 
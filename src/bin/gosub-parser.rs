@@ -42,11 +42,11 @@ fn main() -> Result<()> {
     }
 
     let document = DocumentBuilder::new_document();
-    let parse_errors = Html5Parser::parse_document(&mut chars, Document::clone(&document), None)?;
+    let result = Html5Parser::parse_document(&mut chars, Document::clone(&document), None)?;
 
     println!("Generated tree: \n\n {document}");
 
-    for e in parse_errors {
+    for e in result.errors {
         println!("Parse Error: {}", e.message);
     }
 
@@ -36,7 +36,7 @@ fn main() -> Result<()> {
     }
 
     let document = DocumentBuilder::new_document();
-    let parse_errors = Html5Parser::parse_document(&mut chars, Document::clone(&document), None)?;
+    let result = Html5Parser::parse_document(&mut chars, Document::clone(&document), None)?;
 
     match get_node_by_path(&document.get(), vec!["html", "body"]) {
         None => {
@@ -45,7 +45,7 @@ fn main() -> Result<()> {
         Some(node) => display_node(&document.get(), node),
     }
 
-    for e in parse_errors {
+    for e in result.errors {
         println!("Parse Error: {}", e.message);
     }
 
@@ -1,6 +1,8 @@
-use crate::html5::tokenizer::{CHAR_CR, CHAR_LF};
+use crate::html5::tokenizer::{CHAR_CR, CHAR_LF, CHAR_REPLACEMENT};
+use crate::types::{Error, Result};
 use std::collections::HashMap;
 use std::io::Read;
+use std::ops::Range;
 use std::{fmt, io};
 
 /// Encoding defines the way the buffer stream is read, as what defines a "character".
@@ -47,6 +49,23 @@ impl fmt::Display for Position {
     }
 }
 
+/// A range in the stream, from start (inclusive) to end (exclusive)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    /// Position of the first character
+    pub start: Position,
+    /// Position directly after the last character
+    pub end: Position,
+}
+
+impl Span {
+    /// Create a new span
+    #[must_use]
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+}
+
 /// Defines a single character/element in the stream. This is either a UTF8 character, or
 /// a surrogate characters since these cannot be stored in a single char.
 /// Eof is denoted as a separate element.
@@ -93,6 +112,10 @@ impl Bytes {
     }
 }
 
+/// Number of characters that are kept available after the current position when reading from a
+/// reader, so the tokenizer can always look ahead (ie: for the longest named character reference)
+const READER_LOOKAHEAD: usize = 64;
+
 /// Buffered UTF-8 iterator
 /// TODO: Implement `Peekable` and `Iterator<Item = char>`
 pub struct CharIterator {
@@ -102,12 +125,39 @@ pub struct CharIterator {
     pub confidence: Confidence,
     /// Current positions
     pub position: Position,
-    /// Length (in chars) of the buffer
+    /// Length (in chars) of the stream, including characters that have been discarded
     pub length: usize,
+    /// Number of columns a tab advances to the next tab stop. Only affects the reported columns
+    /// (ie: in parse errors), the default of 1 counts a tab as a single character like the spec.
+    pub tab_width: usize,
+    /// When true, the offset in the original bytes of every character in the stream is tracked
+    /// (see `byte_offset()`). Must be set before reading the input.
+    pub track_byte_offsets: bool,
+    /// When true, a leading byte order mark (U+FEFF) is removed from the input as it is read,
+    /// regardless of how it is read (see `strip_bom()`). Must be set before reading the input.
+    pub strip_leading_bom: bool,
+    /// Offset in the original bytes of each character in the buffer, followed by the total
+    /// number of bytes. Only filled when byte offsets are tracked.
+    byte_offsets: Vec<usize>,
     /// Offsets of the given lines
     line_columns: HashMap<usize, usize>,
     /// Reference to the actual buffer stream in characters
     buffer: Vec<Bytes>,
+    /// Number of characters at the start of the stream that have been dropped from the buffer
+    /// (see `discard_before()`). Offsets in the stream are not affected by this.
+    discarded: usize,
+    /// Column of the first character that is still in the buffer
+    discarded_col: usize,
+    /// Reader the stream is pulled from in chunks while it is being read (see
+    /// `read_from_reader()`). Dropped as soon as the reader is exhausted.
+    source: Option<Box<dyn Read>>,
+    /// Number of bytes requested from the reader at a time
+    source_chunk_size: usize,
+    /// Bytes from the reader that have not been decoded yet, like an incomplete UTF-8 sequence
+    /// or a CR that might be followed by a LF in the next chunk
+    source_pending: Vec<u8>,
+    /// First error returned by the reader. Reading stops at this error.
+    source_error: Option<io::Error>,
     /// Reference to the actual buffer stream in u8 bytes
     u8_buffer: Vec<u8>,
     /// If all things are ok, both buffer and u8_buffer should refer to the same memory location (?)
@@ -133,8 +183,18 @@ impl CharIterator {
                 col: 1,
             },
             length: 0,
+            tab_width: 1,
+            track_byte_offsets: false,
+            strip_leading_bom: false,
+            byte_offsets: Vec::new(),
             line_columns: HashMap::new(),
             buffer: Vec::new(),
+            discarded: 0,
+            discarded_col: 1,
+            source: None,
+            source_chunk_size: 0,
+            source_pending: Vec::new(),
+            source_error: None,
             u8_buffer: Vec::new(),
             has_read_eof: false,
         }
@@ -187,11 +247,159 @@ impl CharIterator {
         pos
     }
 
+    /// Sets the tab width used for column reporting. A width of 0 is treated as 1.
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
+    }
+
+    /// Returns the column directly after a tab that was read on the given column
+    fn next_tab_stop(&self, col: usize) -> usize {
+        let tab_width = self.tab_width.max(1);
+        ((col - 1) / tab_width + 1) * tab_width + 1
+    }
+
+    /// Calculates the column of the given offset by scanning back to the start of its line
+    fn column_at(&self, offset: usize) -> usize {
+        let offset = offset - self.discarded;
+
+        // When the start of the line has been discarded, continue from the first column we know
+        let (line_start, start_col) =
+            match self.buffer[..offset].iter().rposition(|c| *c == Ch('\n')) {
+                Some(pos) => (pos + 1, 1),
+                None => (0, self.discarded_col),
+            };
+
+        self.buffer[line_start..offset]
+            .iter()
+            .fold(start_col, |col, c| match c {
+                Ch('\t') => self.next_tab_stop(col),
+                _ => col + 1,
+            })
+    }
+
+    /// Enables or disables tracking of the original byte offsets. This must be done before the
+    /// input is read.
+    pub fn set_track_byte_offsets(&mut self, enabled: bool) {
+        self.track_byte_offsets = enabled;
+    }
+
+    /// Enables or disables removing a leading byte order mark from the input, as decoding UTF-8
+    /// does in browsers. This must be done before the input is read.
+    pub fn set_strip_leading_bom(&mut self, enabled: bool) {
+        self.strip_leading_bom = enabled;
+    }
+
+    /// Returns the offset in the original bytes of the character at the given stream offset. The
+    /// offset of the stream length is the total number of bytes. Newline normalization is taken
+    /// into account, so a CRLF maps to the offset of its CR. Returns None when byte offsets are
+    /// not tracked, or when the stream has been modified with `insert_str()`.
+    pub fn byte_offset(&self, offset: usize) -> Option<usize> {
+        self.byte_offsets
+            .get(offset.checked_sub(self.discarded)?)
+            .copied()
+    }
+
+    /// Returns the range in the original bytes that is covered by the given span
+    pub fn byte_range(&self, span: &Span) -> Option<Range<usize>> {
+        Some(self.byte_offset(span.start.offset)?..self.byte_offset(span.end.offset)?)
+    }
+
+    /// Calculates the byte offset of each character after newline normalization, from the given
+    /// characters with their byte offsets. The offsets start at base, and end with base + total.
+    fn calculate_byte_offsets(
+        &self,
+        chars: impl Iterator<Item = (usize, char)>,
+        base: usize,
+        total: usize,
+    ) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        let mut last_was_cr = false;
+
+        for (i, c) in chars {
+            // A CRLF is normalized into a single LF, found at the offset of the CR
+            if !(c == CHAR_LF && last_was_cr) {
+                offsets.push(base + i);
+            }
+            last_was_cr = c == CHAR_CR;
+        }
+        offsets.push(base + total);
+
+        offsets
+    }
+
+    /// Drops all characters before the given offset (but never beyond the current position) from
+    /// the buffer. This keeps memory bounded when tokenizing a large stream that is fed in chunks.
+    /// The stream cannot be unread, rewound or sliced before this offset anymore.
+    pub fn discard_before(&mut self, offset: usize) {
+        let offset = offset.min(self.position.offset);
+        if offset <= self.discarded {
+            return;
+        }
+
+        let col = self.column_at(offset);
+        let newlines = self.buffer[offset - self.discarded..self.position.offset - self.discarded]
+            .iter()
+            .filter(|c| **c == Ch('\n'))
+            .count();
+        let line = self.position.line - newlines;
+
+        // Line endings are only needed for lines we can still unread into
+        self.line_columns.retain(|key, _| *key >= line);
+
+        self.buffer.drain(..offset - self.discarded);
+        if !self.byte_offsets.is_empty() {
+            self.byte_offsets.drain(..offset - self.discarded);
+        }
+        self.discarded = offset;
+        self.discarded_col = col;
+    }
+
+    /// Discards the characters before the given offset once at least a chunk of them can be
+    /// dropped. This only does something while reading from a reader, so the buffer stays
+    /// bounded no matter how large the input is.
+    pub fn release_before(&mut self, offset: usize) {
+        if self.source.is_some() && offset.saturating_sub(self.discarded) >= self.source_chunk_size
+        {
+            self.discard_before(offset);
+        }
+    }
+
+    /// Returns the number of characters that are currently kept in the buffer
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
     /// Returns the current offset in the stream
     pub fn tell(&self) -> usize {
         self.position.offset
     }
 
+    /// Moves the stream to the given offset, as returned earlier by `tell()`. Line and column
+    /// are updated accordingly. Returns an error when the offset lies beyond the end of the stream.
+    pub fn rewind_to(&mut self, offset: usize) -> Result<()> {
+        if offset > self.length {
+            return Err(Error::Generic(format!(
+                "cannot rewind to offset {offset}, stream length is {}",
+                self.length
+            )));
+        }
+        if offset < self.discarded {
+            return Err(Error::Generic(format!(
+                "cannot rewind to offset {offset}, it has been discarded"
+            )));
+        }
+
+        self.has_read_eof = false;
+        while self.position.offset > offset {
+            self.unread();
+        }
+        while self.position.offset < offset {
+            self.read_char();
+        }
+
+        Ok(())
+    }
+
     /// Set the given confidence of the input stream encoding
     pub fn set_confidence(&mut self, c: Confidence) {
         self.confidence = c;
@@ -241,11 +449,27 @@ impl CharIterator {
                     })
                     .collect::<Vec<_>>();
                 self.length = self.buffer.len();
+                self.discarded = 0;
+
+                self.byte_offsets = if self.track_byte_offsets {
+                    let s = unsafe { std::str::from_utf8_unchecked(&self.u8_buffer) };
+                    self.calculate_byte_offsets(s.char_indices(), 0, s.len())
+                } else {
+                    Vec::new()
+                };
             }
             Encoding::ASCII => {
                 // Convert the string into characters so we can use easy indexing. Any non-ascii chars (> 0x7F) are converted to '?'
                 self.buffer = self.normalize_newlines_and_ascii(&self.u8_buffer);
                 self.length = self.buffer.len();
+                self.discarded = 0;
+
+                self.byte_offsets = if self.track_byte_offsets {
+                    let chars = self.u8_buffer.iter().map(|b| *b as char).enumerate();
+                    self.calculate_byte_offsets(chars, 0, self.u8_buffer.len())
+                } else {
+                    Vec::new()
+                };
             }
         }
 
@@ -275,28 +499,303 @@ impl CharIterator {
         result
     }
 
+    /// Removes a leading byte order mark (U+FEFF) from the stream, as decoding UTF-8 does in
+    /// browsers. Returns true when a BOM was found. Only possible before anything has been read.
+    pub fn strip_bom(&mut self) -> bool {
+        if self.position.offset != 0 || self.buffer.first() != Some(&Ch('\u{FEFF}')) {
+            return false;
+        }
+
+        self.buffer.remove(0);
+        self.length = self.buffer.len();
+        if !self.byte_offsets.is_empty() {
+            self.byte_offsets.remove(0);
+        }
+        true
+    }
+
+    /// Strips a leading BOM from freshly read input when asked to (see `strip_leading_bom`)
+    fn strip_bom_after_read(&mut self) {
+        if self.strip_leading_bom {
+            self.strip_bom();
+        }
+    }
+
     /// Read directly from bytes
     pub fn read_from_bytes(&mut self, bytes: &[u8], e: Option<Encoding>) -> io::Result<()> {
+        self.source = None;
         self.u8_buffer = bytes.to_vec();
         self.force_set_encoding(e.unwrap_or(Encoding::UTF8));
         self.reset();
+        self.strip_bom_after_read();
         Ok(())
     }
 
     /// Populates the current buffer with the contents of given file f
     pub fn read_from_file(&mut self, mut f: impl Read, e: Option<Encoding>) -> io::Result<()> {
+        self.source = None;
         // First we read the u8 bytes into a buffer
         f.read_to_end(&mut self.u8_buffer).expect("uh oh");
         self.force_set_encoding(e.unwrap_or(Encoding::UTF8));
         self.reset();
+        self.strip_bom_after_read();
         Ok(())
     }
 
+    /// Reads the stream from the given reader in chunks of `chunk_size` bytes. Unlike
+    /// `read_from_file()`, the input is only pulled from the reader when it is about to be read.
+    /// Together with `release_before()` this allows tokenizing input of any size without keeping
+    /// it in memory.
+    ///
+    /// Reading stops at the first error of the reader, see `take_read_error()`.
+    pub fn read_from_reader(
+        &mut self,
+        reader: impl Read + 'static,
+        chunk_size: usize,
+        e: Option<Encoding>,
+    ) {
+        self.u8_buffer.clear();
+        self.buffer.clear();
+        self.line_columns.clear();
+        self.length = 0;
+        self.discarded = 0;
+        self.discarded_col = 1;
+        self.byte_offsets = if self.track_byte_offsets {
+            vec![0]
+        } else {
+            Vec::new()
+        };
+        self.encoding = e.unwrap_or(Encoding::UTF8);
+
+        self.source = Some(Box::new(reader));
+        self.source_chunk_size = chunk_size.max(1);
+        self.source_pending.clear();
+        self.source_error = None;
+
+        self.reset();
+        self.has_read_eof = false;
+        self.fill_from_reader();
+        self.strip_bom_after_read();
+    }
+
+    /// Returns the error the reader returned, if any. The stream ends at the point of the error.
+    pub fn take_read_error(&mut self) -> Option<io::Error> {
+        self.source_error.take()
+    }
+
+    /// Pulls chunks from the reader until enough characters are available to look ahead, or
+    /// until the reader is exhausted
+    fn fill_from_reader(&mut self) {
+        while self.length - self.position.offset < READER_LOOKAHEAD {
+            let Some(source) = self.source.as_mut() else {
+                return;
+            };
+
+            let start = self.source_pending.len();
+            self.source_pending
+                .resize(start + self.source_chunk_size, 0);
+            let read = match source.read(&mut self.source_pending[start..]) {
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    self.source_pending.truncate(start);
+                    continue;
+                }
+                Err(err) => {
+                    self.source_error = Some(err);
+                    0
+                }
+            };
+            self.source_pending.truncate(start + read);
+
+            if read == 0 {
+                // Nothing more will follow, so whatever is pending is decoded as is
+                self.source = None;
+                let pending = std::mem::take(&mut self.source_pending);
+                let text = match self.encoding {
+                    Encoding::UTF8 => String::from_utf8_lossy(&pending).into_owned(),
+                    Encoding::ASCII => pending.iter().map(|b| *b as char).collect(),
+                };
+                self.append_str(&text);
+                return;
+            }
+
+            let text = self.decode_pending();
+            self.append_str(&text);
+        }
+    }
+
+    /// Decodes as much of the pending bytes as possible. Invalid UTF-8 sequences are replaced
+    /// with U+FFFD, while an incomplete sequence or CR at the end is kept until the next chunk.
+    fn decode_pending(&mut self) -> String {
+        let mut text = String::new();
+        let mut consumed = 0;
+
+        match self.encoding {
+            Encoding::UTF8 => loop {
+                match std::str::from_utf8(&self.source_pending[consumed..]) {
+                    Ok(valid) => {
+                        text.push_str(valid);
+                        consumed = self.source_pending.len();
+                        break;
+                    }
+                    Err(err) => {
+                        let valid_end = consumed + err.valid_up_to();
+                        text.push_str(unsafe {
+                            std::str::from_utf8_unchecked(&self.source_pending[consumed..valid_end])
+                        });
+                        consumed = valid_end;
+
+                        match err.error_len() {
+                            Some(len) => {
+                                text.push(CHAR_REPLACEMENT);
+                                consumed += len;
+                            }
+                            None => break,
+                        }
+                    }
+                }
+            },
+            Encoding::ASCII => {
+                text.extend(self.source_pending.iter().map(|b| *b as char));
+                consumed = self.source_pending.len();
+            }
+        }
+
+        // A CRLF must be normalized as a whole, so a trailing CR waits for the next chunk
+        if text.ends_with(CHAR_CR) {
+            text.pop();
+            consumed -= 1;
+        }
+
+        self.source_pending.drain(..consumed);
+        text
+    }
+
     /// Populates the current buffer with the contents of the given string s
     pub fn read_from_str(&mut self, s: &str, e: Option<Encoding>) {
+        self.source = None;
         self.u8_buffer = Vec::from(s.as_bytes());
         self.force_set_encoding(e.unwrap_or(Encoding::UTF8));
         self.reset();
+        self.strip_bom_after_read();
+    }
+
+    /// Populates the current buffer with already decoded characters, so no encoding is needed.
+    /// Newlines are normalized the same way as when reading the stream.
+    pub fn read_from_chars(&mut self, chars: &[char]) {
+        self.u8_buffer.clear();
+        self.byte_offsets.clear();
+        self.discarded = 0;
+        self.source = None;
+        self.buffer = Vec::with_capacity(chars.len());
+
+        for (i, &c) in chars.iter().enumerate() {
+            if c == CHAR_CR {
+                // convert CR to LF, or CRLF to LF
+                if chars.get(i + 1) == Some(&CHAR_LF) {
+                    continue;
+                }
+                self.buffer.push(Ch(CHAR_LF));
+            } else {
+                self.buffer.push(Ch(c));
+            }
+        }
+
+        self.length = self.buffer.len();
+        self.encoding = Encoding::UTF8;
+        self.reset();
+        self.strip_bom_after_read();
+    }
+
+    /// Populates the current buffer with UTF-16 code units (ie: strings coming from javascript).
+    /// Surrogate pairs are combined into a single character, and unpaired surrogates are replaced
+    /// with U+FFFD while decoding, like any other decoding error. Newlines are normalized as usual.
+    pub fn read_from_utf16(&mut self, units: &[u16]) {
+        self.u8_buffer.clear();
+        self.byte_offsets.clear();
+        self.discarded = 0;
+        self.source = None;
+        self.buffer = Vec::with_capacity(units.len());
+
+        let mut last_was_cr = false;
+        for c in char::decode_utf16(units.iter().copied()) {
+            match &c {
+                // convert CR to LF, or CRLF to LF
+                Ok(CHAR_LF) if last_was_cr => {}
+                Ok(CHAR_CR) => self.buffer.push(Ch(CHAR_LF)),
+                Ok(c) => self.buffer.push(Ch(*c)),
+                Err(_) => self.buffer.push(Ch(CHAR_REPLACEMENT)),
+            }
+            last_was_cr = c == Ok(CHAR_CR);
+        }
+
+        self.length = self.buffer.len();
+        self.encoding = Encoding::UTF8;
+        self.reset();
+        self.strip_bom_after_read();
+    }
+
+    /// Inserts the given string at the current position in the stream, so that it will be read
+    /// next. Newlines are normalized the same way as when reading the stream.
+    pub fn insert_str(&mut self, s: &str) {
+        self.insert_str_at(self.position.offset, s);
+    }
+
+    /// Inserts the given string at the given offset in the stream. The offset must not lie
+    /// before the current position.
+    pub(crate) fn insert_str_at(&mut self, offset: usize, s: &str) {
+        let chars = self.normalize_str(s);
+
+        let offset = offset - self.discarded;
+        self.buffer.splice(offset..offset, chars);
+        self.length = self.discarded + self.buffer.len();
+
+        // Inserted text is not part of the original bytes
+        self.byte_offsets.clear();
+
+        // Anything inserted at the end of the stream means we are no longer at EOF
+        self.has_read_eof = false;
+    }
+
+    /// Removes the characters in the given range of offsets from the stream, ie: text inserted
+    /// earlier with `insert_str()`. The range must not start before the current position.
+    pub(crate) fn remove_range(&mut self, range: Range<usize>) {
+        let start = range.start - self.discarded;
+        let end = range.end - self.discarded;
+        self.buffer.drain(start..end);
+        self.length = self.discarded + self.buffer.len();
+    }
+
+    /// Appends the given string to the end of the stream. This is used when the input arrives
+    /// in chunks. Newlines are normalized the same way as when reading the stream.
+    pub fn append_str(&mut self, s: &str) {
+        let chars = self.normalize_str(s);
+
+        self.buffer.extend(chars);
+        self.length = self.discarded + self.buffer.len();
+
+        // The appended bytes directly follow the bytes read so far
+        if let Some(total) = self.byte_offsets.pop() {
+            let offsets = self.calculate_byte_offsets(s.char_indices(), total, s.len());
+            self.byte_offsets.extend(offsets);
+        }
+
+        self.has_read_eof = false;
+    }
+
+    /// Converts the given string into stream characters, normalizing newlines
+    fn normalize_str(&self, s: &str) -> Vec<Bytes> {
+        let normalized = s
+            .replace("\u{000D}\u{000A}", "\u{000A}")
+            .replace('\u{000D}', "\u{000A}");
+
+        normalized
+            .chars()
+            .map(|c| match self.encoding {
+                Encoding::ASCII if !c.is_ascii() => Ch('?'),
+                _ => Ch(c),
+            })
+            .collect()
     }
 
     /// Returns the number of characters left in the buffer
@@ -312,20 +811,19 @@ impl CharIterator {
             return Eof;
         }
 
+        if self.source.is_some() {
+            self.fill_from_reader();
+        }
+
         // If we still can move forward in the stream, move forwards
         if self.position.offset < self.length {
-            let c = self.buffer[self.position.offset];
+            let c = self.buffer[self.position.offset - self.discarded];
             if c == Ch('\n') {
                 // Store line offset for the given line
                 self.line_columns
                     .insert(self.position.line, self.position.col);
-                // And continue position on the next line
-                self.position.line += 1;
-                self.position.col = 1;
-            } else {
-                self.position.col += 1;
             }
-            self.position.offset += 1;
+            self.position = self.position_after(self.position, c);
             return c;
         }
 
@@ -343,24 +841,68 @@ impl CharIterator {
         }
 
         // If we can track back from the offset, we can do so
-        if self.position.offset > 0 {
+        if self.position.offset > self.discarded {
             self.position.offset -= 1;
 
             if self.position.col == 1 {
                 self.position.line -= 1;
                 let key = self.position.line;
                 self.position.col = *self.line_columns.get(&key).unwrap_or(&1);
+            } else if self.buffer[self.position.offset - self.discarded] == Ch('\t') {
+                self.position.col = self.column_at(self.position.offset);
             } else {
                 self.position.col -= 1;
             }
         }
     }
 
+    /// Returns the position of the character following the given character at the given position
+    pub(crate) fn position_after(&self, pos: Position, c: Bytes) -> Position {
+        let (line, col) = match c {
+            // Continue on the next line
+            Ch('\n') => (pos.line + 1, 1),
+            Ch('\t') => (pos.line, self.next_tab_stop(pos.col)),
+            _ => (pos.line, pos.col + 1),
+        };
+
+        Position::new(pos.offset + 1, line, col)
+    }
+
+    /// Reads characters up to (but not including) the given terminator, or up to the end of the
+    /// stream when the terminator is not found. Reading also stops at a surrogate, since it cannot
+    /// be part of a string.
+    pub fn read_until(&mut self, terminator: char) -> String {
+        let mut result = String::new();
+        loop {
+            match self.look_ahead(0) {
+                Ch(c) if c != terminator => {
+                    self.read_char();
+                    result.push(c);
+                }
+                _ => break,
+            }
+        }
+
+        result
+    }
+
+    /// Returns the characters between the start (inclusive) and end (exclusive) offsets
+    pub(crate) fn slice(&self, start: usize, end: usize) -> String {
+        // Discarded characters are no longer available
+        let end = std::cmp::min(self.length, end).max(self.discarded);
+        let start = start.clamp(self.discarded, end);
+
+        self.buffer[start - self.discarded..end - self.discarded]
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
     /// Looks ahead in the stream and returns len characters
     pub(crate) fn look_ahead_slice(&self, len: usize) -> String {
         let end_pos = std::cmp::min(self.length, self.position.offset + len);
 
-        let slice = &self.buffer[self.position.offset..end_pos];
+        let slice = &self.buffer[self.position.offset - self.discarded..end_pos - self.discarded];
         slice.iter().map(ToString::to_string).collect()
     }
 
@@ -372,7 +914,21 @@ impl CharIterator {
             return Eof;
         }
 
-        self.buffer[self.position.offset + offset]
+        self.buffer[self.position.offset + offset - self.discarded]
+    }
+
+    /// Looks back in the stream at characters that have already been read. An offset of 1
+    /// returns the last read character. Returns None when looking before the start of the
+    /// stream, or when the character found is not a regular character (ie: a surrogate).
+    pub fn look_behind(&self, offset: usize) -> Option<char> {
+        if offset == 0 || offset > self.position.offset - self.discarded {
+            return None;
+        }
+
+        match self.buffer[self.position.offset - self.discarded - offset] {
+            Ch(c) => Some(c),
+            _ => None,
+        }
     }
 }
 
@@ -443,6 +999,274 @@ mod test {
         assert!(matches!(chars.read_char(), Eof));
     }
 
+    #[test]
+    fn test_look_behind() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a--b", Some(Encoding::UTF8));
+
+        // Nothing has been read yet
+        assert_eq!(chars.look_behind(0), None);
+        assert_eq!(chars.look_behind(1), None);
+
+        assert_eq!(chars.read_char(), Ch('a'));
+        assert_eq!(chars.look_behind(1), Some('a'));
+        assert_eq!(chars.look_behind(2), None);
+
+        chars.skip(2);
+        assert_eq!(chars.look_behind(1), Some('-'));
+        assert_eq!(chars.look_behind(2), Some('-'));
+        assert_eq!(chars.look_behind(3), Some('a'));
+        assert_eq!(chars.look_behind(4), None);
+
+        assert_eq!(chars.read_char(), Ch('b'));
+        assert!(matches!(chars.read_char(), Eof));
+        assert_eq!(chars.look_behind(1), Some('b'));
+
+        chars.reset();
+        assert_eq!(chars.look_behind(1), None);
+    }
+
+    #[test]
+    fn test_insert_str() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("ad", Some(Encoding::UTF8));
+
+        assert_eq!(chars.read_char(), Ch('a'));
+        chars.insert_str("b\r\nc");
+        assert_eq!(chars.length, 5);
+        assert_eq!(chars.read_char(), Ch('b'));
+        assert_eq!(chars.read_char(), Ch('\n'));
+        assert_eq!(chars.read_char(), Ch('c'));
+        assert_eq!(chars.read_char(), Ch('d'));
+        assert!(matches!(chars.read_char(), Eof));
+
+        // Inserting after reaching EOF makes the new characters available
+        chars.insert_str("e");
+        assert_eq!(chars.read_char(), Ch('e'));
+        assert!(matches!(chars.read_char(), Eof));
+    }
+
+    #[test]
+    fn test_byte_offsets() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a\r\nb", None);
+        assert_eq!(chars.byte_offset(0), None);
+
+        let mut chars = CharIterator::new();
+        chars.set_track_byte_offsets(true);
+        chars.read_from_str("a\r\né\rc", None);
+
+        // a, LF (from CRLF), é, LF (from CR), c
+        assert_eq!(chars.length, 5);
+        let offsets: Vec<_> = (0..=5).filter_map(|i| chars.byte_offset(i)).collect();
+        assert_eq!(offsets, vec![0, 1, 3, 5, 6, 7]);
+
+        // A stripped BOM is not part of the stream, but still part of the original bytes
+        let mut chars = CharIterator::new();
+        chars.set_track_byte_offsets(true);
+        chars.set_strip_leading_bom(true);
+        chars
+            .read_from_bytes("\u{FEFF}ab".as_bytes(), None)
+            .unwrap();
+        assert_eq!(chars.byte_offset(0), Some(3));
+        assert_eq!(chars.byte_offset(2), Some(5));
+
+        // Appended chunks continue where the previous bytes ended
+        chars.append_str("\r\nc");
+        assert_eq!(chars.byte_offset(2), Some(5));
+        assert_eq!(chars.byte_offset(3), Some(7));
+        assert_eq!(chars.byte_offset(4), Some(8));
+
+        // Inserted text has no original bytes
+        chars.insert_str("x");
+        assert_eq!(chars.byte_offset(0), None);
+    }
+
+    #[test]
+    fn test_read_from_chars() {
+        let mut chars = CharIterator::new();
+        chars.read_from_chars(&['a', '\r', '\n', 'b', '\r', 'c']);
+
+        assert_eq!(chars.length, 5);
+        assert_eq!(chars.read_char(), Ch('a'));
+        assert_eq!(chars.read_char(), Ch('\n'));
+        assert_eq!(chars.read_char(), Ch('b'));
+        assert_eq!(chars.read_char(), Ch('\n'));
+        assert_eq!(chars.read_char(), Ch('c'));
+        assert!(matches!(chars.read_char(), Eof));
+    }
+
+    #[test]
+    fn test_append_str() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("ab", Some(Encoding::UTF8));
+
+        assert_eq!(chars.read_char(), Ch('a'));
+        chars.append_str("c\r\nd");
+        assert_eq!(chars.length, 5);
+        assert_eq!(chars.read_char(), Ch('b'));
+        assert_eq!(chars.read_char(), Ch('c'));
+        assert_eq!(chars.read_char(), Ch('\n'));
+        assert_eq!(chars.read_char(), Ch('d'));
+        assert!(matches!(chars.read_char(), Eof));
+
+        chars.append_str("e");
+        assert_eq!(chars.read_char(), Ch('e'));
+        assert!(matches!(chars.read_char(), Eof));
+    }
+
+    #[test]
+    fn test_read_from_reader() {
+        let mut chars = CharIterator::new();
+        chars.set_track_byte_offsets(true);
+        chars.set_strip_leading_bom(true);
+        chars.read_from_reader(&b"\xEF\xBB\xBFa\r\n\xC3\xA9\xFF\r"[..], 1, None);
+
+        assert_eq!(chars.read_char(), Ch('a'));
+        assert_eq!(chars.read_char(), Ch('\n'));
+        assert_eq!(chars.read_char(), Ch('é'));
+        assert_eq!(chars.read_char(), Ch('\u{FFFD}'));
+        assert_eq!(chars.read_char(), Ch('\n'));
+        assert!(matches!(chars.read_char(), Eof));
+        assert_eq!(chars.byte_offset(2), Some(6));
+        assert!(chars.take_read_error().is_none());
+
+        let input = "x".repeat(200);
+        let mut chars = CharIterator::new();
+        chars.read_from_reader(std::io::Cursor::new(input), 10, None);
+        assert_eq!(chars.buffered_len(), 70);
+
+        chars.skip(30);
+        chars.release_before(25);
+        assert_eq!(chars.buffered_len(), 75);
+        assert!(chars.rewind_to(24).is_err());
+        assert!(chars.rewind_to(25).is_ok());
+        assert_eq!(chars.position.col, 26);
+    }
+
+    #[test]
+    fn test_read_from_utf16() {
+        let mut chars = CharIterator::new();
+        // "a", an unpaired high surrogate, "b\r\n", a surrogate pair and a lone low surrogate
+        chars.read_from_utf16(&[0x61, 0xD800, 0x62, 0x0D, 0x0A, 0xD83D, 0xDE00, 0xDC00]);
+
+        assert_eq!(chars.length, 6);
+        assert_eq!(chars.read_char(), Ch('a'));
+        assert_eq!(chars.read_char(), Ch(CHAR_REPLACEMENT));
+        assert_eq!(chars.read_char(), Ch('b'));
+        assert_eq!(chars.read_char(), Ch('\n'));
+        assert_eq!(chars.read_char(), Ch('\u{1F600}'));
+        assert_eq!(chars.read_char(), Ch(CHAR_REPLACEMENT));
+        assert!(matches!(chars.read_char(), Eof));
+    }
+
+    #[test]
+    fn test_strip_bom() {
+        // The BOM is kept by default, no matter how the input is read
+        let mut chars = CharIterator::new();
+        chars
+            .read_from_bytes(b"\xEF\xBB\xBFab", Some(Encoding::UTF8))
+            .unwrap();
+        assert_eq!(chars.length, 3);
+
+        let mut chars = CharIterator::new();
+        chars.set_strip_leading_bom(true);
+        chars
+            .read_from_bytes(b"\xEF\xBB\xBFab", Some(Encoding::UTF8))
+            .unwrap();
+        assert_eq!(chars.length, 2);
+        assert_eq!(chars.read_char(), Ch('a'));
+
+        let mut chars = CharIterator::new();
+        chars.set_strip_leading_bom(true);
+        chars.read_from_utf16(&[0xFEFF, 0x61]);
+        assert_eq!(chars.length, 1);
+
+        // Only a leading BOM is stripped
+        let mut chars = CharIterator::new();
+        chars.set_strip_leading_bom(true);
+        chars.read_from_str("a\u{FEFF}", Some(Encoding::UTF8));
+        assert_eq!(chars.length, 2);
+
+        // Without the option, the BOM can still be stripped explicitly
+        let mut chars = CharIterator::new();
+        chars.read_from_str("\u{FEFF}a\u{FEFF}", Some(Encoding::UTF8));
+        assert_eq!(chars.length, 3);
+        assert!(chars.strip_bom());
+        assert!(!chars.strip_bom());
+        assert_eq!(chars.read_char(), Ch('a'));
+        assert_eq!(chars.read_char(), Ch('\u{FEFF}'));
+    }
+
+    #[test]
+    fn test_rewind_to() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("ab\ncd", Some(Encoding::UTF8));
+
+        chars.skip(2);
+        let mark = chars.tell();
+        chars.skip(3);
+        assert!(matches!(chars.read_char(), Eof));
+
+        chars.rewind_to(mark).unwrap();
+        assert_eq!(chars.position, Position::new(2, 1, 3));
+        assert_eq!(chars.read_char(), Ch('\n'));
+
+        chars.rewind_to(5).unwrap();
+        assert_eq!(chars.position, Position::new(5, 2, 3));
+
+        assert!(chars.rewind_to(6).is_err());
+        assert_eq!(chars.position.offset, 5);
+    }
+
+    #[test]
+    fn test_read_until() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("abc>def", Some(Encoding::UTF8));
+
+        assert_eq!(chars.read_until('>'), "abc");
+        assert_eq!(chars.position.offset, 3);
+        assert_eq!(chars.look_ahead(0), Ch('>'));
+
+        // Nothing to read when already at the terminator
+        assert_eq!(chars.read_until('>'), "");
+        chars.skip(1);
+        assert_eq!(chars.read_until('>'), "def");
+        assert!(chars.eof());
+
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a\n\tb>", Some(Encoding::UTF8));
+        chars.set_tab_width(4);
+        assert_eq!(chars.read_until('>'), "a\n\tb");
+        assert_eq!(chars.position, Position::new(4, 2, 6));
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("\ta\n\t\tb", Some(Encoding::UTF8));
+        chars.set_tab_width(4);
+
+        assert_eq!(chars.read_char(), Ch('\t'));
+        assert_eq!(chars.position, Position::new(1, 1, 5));
+        assert_eq!(chars.read_char(), Ch('a'));
+        assert_eq!(chars.position.col, 6);
+        chars.unread();
+        chars.unread();
+        assert_eq!(chars.position, Position::new(0, 1, 1));
+
+        chars.skip(5);
+        assert_eq!(chars.position, Position::new(5, 2, 9));
+        chars.unread();
+        assert_eq!(chars.position, Position::new(4, 2, 5));
+
+        // The default counts a tab as a single column
+        let mut chars = CharIterator::new();
+        chars.read_from_str("\ta", Some(Encoding::UTF8));
+        chars.read_char();
+        assert_eq!(chars.position.col, 2);
+    }
+
     #[test]
     fn test_certainty() {
         let mut chars = CharIterator::new();
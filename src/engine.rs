@@ -136,8 +136,8 @@ fn fetch_url(
     fetch_response.document = DocumentBuilder::new_document();
 
     match Html5Parser::parse_document(&mut chars, Document::clone(&fetch_response.document), None) {
-        Ok(parse_errors) => {
-            fetch_response.parse_errors = parse_errors;
+        Ok(result) => {
+            fetch_response.parse_errors = result.errors;
         }
         Err(e) => {
             return Err(Error::Generic(format!("Failed to parse HTML: {}", e)));
@@ -7,5 +7,7 @@ pub mod element_class;
 pub mod error_logger;
 pub mod node;
 pub mod parser;
+pub mod rewriter;
+pub mod serialize;
 pub mod tokenizer;
 pub mod util;
@@ -153,10 +153,63 @@ impl ParserError {
     }
 }
 
+/// Diagnostics are reported for constructs that are legal according to the spec, but that some
+/// consumers want to be warned about. They are never parse errors.
+pub enum Diagnostic {
+    GreaterThanSignInAttributeValue,
+}
+
+impl Diagnostic {
+    /// Diagnostics as string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Diagnostic::GreaterThanSignInAttributeValue => "greater-than-sign-in-attribute-value",
+        }
+    }
+}
+
+/// Receives the parse errors found by the tokenizer. Implement this to count, filter or report
+/// errors as they are found, instead of storing them all.
+pub trait ParseErrorSink {
+    /// Handles a parse error found at the given position
+    fn add_error(&mut self, pos: Position, message: &str);
+
+    /// Returns the errors kept by this sink. Sinks that do not store errors return nothing.
+    fn get_errors(&self) -> Vec<ParseError> {
+        vec![]
+    }
+
+    /// Handles a diagnostic (see `Diagnostic`) found at the given position. Diagnostics are kept
+    /// apart from the parse errors, and are dropped unless a sink handles them.
+    fn add_diagnostic(&mut self, _pos: Position, _message: &str) {}
+
+    /// Returns the diagnostics kept by this sink
+    fn get_diagnostics(&self) -> Vec<ParseError> {
+        vec![]
+    }
+
+    /// Returns a mark for the errors handled so far, which can be passed to `rollback()` later.
+    /// The tokenizer takes a mark for every checkpoint. What the mark means is up to the sink,
+    /// ie: the number of errors stored or counted.
+    fn mark(&self) -> usize {
+        0
+    }
+
+    /// Forgets all errors handled since the given mark was taken. Called when the tokenizer is
+    /// restored to a checkpoint, so errors found after the checkpoint are not handled twice.
+    /// Sinks that store or count errors should implement this together with `mark()`.
+    fn rollback(&mut self, _mark: usize) {}
+}
+
+/// The default sink, which stores every (unique) parse error in a list
+pub type VecSink = ErrorLogger;
+
 #[derive(Clone)]
 pub struct ErrorLogger {
     /// List of errors that occurred during parsing
     errors: Vec<ParseError>,
+    /// List of diagnostics, which are not parse errors
+    diagnostics: Vec<ParseError>,
 }
 
 impl Default for ErrorLogger {
@@ -169,7 +222,10 @@ impl ErrorLogger {
     /// Creates a new error logger
     #[must_use]
     pub fn new() -> Self {
-        Self { errors: Vec::new() }
+        Self {
+            errors: Vec::new(),
+            diagnostics: Vec::new(),
+        }
     }
 
     /// Returns a cloned instance of the errors
@@ -195,6 +251,54 @@ impl ErrorLogger {
 
         // println!("Parse error ({}/{}): {}", pos.line, pos.col, message);
     }
+
+    /// Returns a cloned instance of the diagnostics
+    pub fn get_diagnostics(&self) -> Vec<ParseError> {
+        self.diagnostics.clone()
+    }
+
+    /// Adds a new diagnostic to the error logger
+    pub fn add_diagnostic(&mut self, pos: Position, message: &str) {
+        // Diagnostics found again after restoring a checkpoint are only stored once
+        for diag in &self.diagnostics {
+            if diag.line == pos.line && diag.col == pos.col && diag.message == *message {
+                return;
+            }
+        }
+
+        self.diagnostics.push(ParseError {
+            line: pos.line,
+            col: pos.col,
+            offset: pos.offset,
+            message: message.to_string(),
+        });
+    }
+}
+
+impl ParseErrorSink for ErrorLogger {
+    fn add_error(&mut self, pos: Position, message: &str) {
+        ErrorLogger::add_error(self, pos, message);
+    }
+
+    fn get_errors(&self) -> Vec<ParseError> {
+        ErrorLogger::get_errors(self)
+    }
+
+    fn add_diagnostic(&mut self, pos: Position, message: &str) {
+        ErrorLogger::add_diagnostic(self, pos, message);
+    }
+
+    fn get_diagnostics(&self) -> Vec<ParseError> {
+        ErrorLogger::get_diagnostics(self)
+    }
+
+    fn mark(&self) -> usize {
+        self.errors.len()
+    }
+
+    fn rollback(&mut self, mark: usize) {
+        self.errors.truncate(mark);
+    }
 }
 
 #[cfg(test)]
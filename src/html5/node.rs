@@ -20,6 +20,49 @@ pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
 pub mod arena;
 pub mod data;
 
+/// Namespaces that can be found in a document
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Namespace {
+    Html,
+    MathMl,
+    Svg,
+    XLink,
+    Xml,
+    XmlNs,
+}
+
+impl Namespace {
+    /// Returns the namespace URI
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Namespace::Html => HTML_NAMESPACE,
+            Namespace::MathMl => MATHML_NAMESPACE,
+            Namespace::Svg => SVG_NAMESPACE,
+            Namespace::XLink => XLINK_NAMESPACE,
+            Namespace::Xml => XML_NAMESPACE,
+            Namespace::XmlNs => XMLNS_NAMESPACE,
+        }
+    }
+
+    /// Returns the namespace for the given URI, or None when the URI is unknown
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        match uri {
+            HTML_NAMESPACE => Some(Namespace::Html),
+            MATHML_NAMESPACE => Some(Namespace::MathMl),
+            SVG_NAMESPACE => Some(Namespace::Svg),
+            XLINK_NAMESPACE => Some(Namespace::XLink),
+            XML_NAMESPACE => Some(Namespace::Xml),
+            XMLNS_NAMESPACE => Some(Namespace::XmlNs),
+            _ => None,
+        }
+    }
+
+    /// Returns true when this is a foreign (non-HTML) content namespace
+    pub fn is_foreign(&self) -> bool {
+        matches!(self, Namespace::MathMl | Namespace::Svg)
+    }
+}
+
 /// Different types of nodes
 #[derive(Debug, PartialEq)]
 pub enum NodeType {
@@ -302,9 +345,14 @@ impl Node {
         }
     }
 
+    /// Returns the namespace of the node, or None when the node has no (known) namespace
+    pub fn get_namespace(&self) -> Option<Namespace> {
+        self.namespace.as_deref().and_then(Namespace::from_uri)
+    }
+
     /// Returns true when the given node is of the given namespace
     pub(crate) fn is_namespace(&self, namespace: &str) -> bool {
-        self.namespace == Some(namespace.into())
+        self.namespace.as_deref() == Some(namespace)
     }
 
     /// Returns true if the given node is a html integration point
@@ -495,6 +543,26 @@ mod tests {
         assert_eq!(element.attributes.get("id").unwrap(), "test");
     }
 
+    #[test]
+    fn namespace() {
+        for ns in [
+            Namespace::Html,
+            Namespace::MathMl,
+            Namespace::Svg,
+            Namespace::XLink,
+            Namespace::Xml,
+            Namespace::XmlNs,
+        ] {
+            assert_eq!(Namespace::from_uri(ns.as_str()), Some(ns));
+        }
+        assert_eq!(Namespace::from_uri("urn:unknown"), None);
+
+        let document = Document::shared();
+        let node = Node::new_element(&document, "svg", HashMap::new(), SVG_NAMESPACE);
+        assert_eq!(node.get_namespace(), Some(Namespace::Svg));
+        assert!(node.get_namespace().unwrap().is_foreign());
+    }
+
     #[test]
     fn new_comment() {
         let document = Document::shared();
@@ -519,7 +587,7 @@ mod tests {
         assert!(node.children.is_empty());
         assert_eq!(node.name, "".to_string());
         assert_eq!(node.namespace, None);
-        let NodeData::Text(TextData { value }) = &node.data else {
+        let NodeData::Text(TextData { value, .. }) = &node.data else {
             panic!()
         };
         assert_eq!(value, "test");
@@ -3,6 +3,9 @@
 pub struct TextData {
     /// Actual text
     pub(crate) value: String,
+    /// Text as written in the source, with character references intact. Only kept for text in
+    /// title and textarea when the parser runs with `preserve_rcdata_source`.
+    pub(crate) source: Option<String>,
 }
 
 impl Default for TextData {
@@ -16,16 +19,23 @@ impl TextData {
     pub(crate) fn new() -> Self {
         Self {
             value: String::new(),
+            source: None,
         }
     }
 
     pub(crate) fn with_value(value: &str) -> Self {
         Self {
             value: value.to_owned(),
+            source: None,
         }
     }
 
     pub fn value(&self) -> &str {
         &self.value
     }
+
+    /// Returns the text as written in the source, if it has been kept
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
 }
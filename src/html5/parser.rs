@@ -10,15 +10,19 @@ use self::document::DocumentHandle;
 use super::node::NodeId;
 use crate::bytes::CharIterator;
 use crate::html5::error_logger::{ErrorLogger, ParserError};
-use crate::html5::node::{Node, NodeData, HTML_NAMESPACE, MATHML_NAMESPACE, SVG_NAMESPACE};
+use crate::html5::node::{
+    Namespace, Node, NodeData, HTML_NAMESPACE, MATHML_NAMESPACE, SVG_NAMESPACE,
+};
 use crate::html5::parser::attr_replacements::{
     MATHML_ADJUSTMENTS, SVG_ADJUSTMENTS_ATTRIBUTES, SVG_ADJUSTMENTS_TAGS, XML_ADJUSTMENTS,
 };
 use crate::html5::parser::document::{Document, DocumentBuilder, DocumentFragment, DocumentType};
 use crate::html5::parser::quirks::QuirksMode;
+use crate::html5::parser::tree_builder::{TreeBuilder, TreeBuilderSink};
 use crate::html5::tokenizer::state::State;
-use crate::html5::tokenizer::token::Token;
+use crate::html5::tokenizer::token::{Attribute, CommentClosing, Token};
 use crate::html5::tokenizer::{ParserData, Tokenizer, CHAR_REPLACEMENT};
+use crate::html5::util::ascii_case_insensitive_eq;
 use crate::types::{ParseError, Result};
 use alloc::rc::Rc;
 use core::cell::RefCell;
@@ -141,18 +145,83 @@ impl ActiveElement {
     }
 }
 
+/// Default maximum depth of the tree of open elements (see `Html5ParserOptions::max_tree_depth`)
+pub const DEFAULT_MAX_TREE_DEPTH: usize = 512;
+/// Lowest accepted maximum depth of the tree of open elements. Lower values are raised to this,
+/// so <html> and <body> always end up in their regular place.
+pub const MIN_TREE_DEPTH: usize = 3;
+
 pub struct Html5ParserOptions {
     pub scripting_enabled: bool,
+    /// Maximum number of open elements. Once reached, the current node is closed before a new
+    /// element is inserted, so the element ends up next to it instead of inside it. This keeps
+    /// deeply nested input (ie: thousands of `<div>`s) from building an unbounded tree. Values
+    /// below `MIN_TREE_DEPTH` are raised to it.
+    pub max_tree_depth: usize,
+    /// When parsing a fragment, a DOCTYPE is a parse error and ignored. With this option, it is
+    /// inserted as a comment instead (ie: `<!--DOCTYPE html-->`), so tools can detect stray
+    /// doctypes in fragments. The parse error is reported either way.
+    pub fragment_doctype_as_comment: bool,
+    /// Keeps the source of text in title and textarea next to the decoded text, so serializing
+    /// reproduces their character references as written (ie: `&copy;` instead of `©`)
+    pub preserve_rcdata_source: bool,
+    /// Custom elements (ie: `my-icon`) that are built like void elements: they never get any
+    /// children, and a trailing solidus in their start tag is acknowledged. Register the same
+    /// elements with `SerializeOpts::add_void_element()` to serialize them without end tag.
+    pub custom_void_elements: Vec<String>,
 }
 
 impl Default for Html5ParserOptions {
     fn default() -> Self {
         Self {
             scripting_enabled: true,
+            max_tree_depth: DEFAULT_MAX_TREE_DEPTH,
+            fragment_doctype_as_comment: false,
+            preserve_rcdata_source: false,
+            custom_void_elements: vec![],
         }
     }
 }
 
+/// Result of parsing a document: the document itself, together with the parse errors found
+pub struct ParseResult {
+    /// Handle to the parsed document
+    pub document: DocumentHandle,
+    /// Parse errors found by both the tokenizer and the parser
+    pub errors: Vec<ParseError>,
+}
+
+/// Returns true when the input parses as a fragment inside the given (HTML) context element
+/// without any parse errors. Useful for sanitizers that want to reject sloppy markup.
+pub fn is_well_formed_fragment(input: &str, context: &str) -> bool {
+    let mut chars = CharIterator::new();
+    chars.read_from_str(input, None);
+
+    // The context element lives in a main document of its own
+    let mut main_document = DocumentBuilder::new_document();
+    let context_node_id =
+        main_document.create_element(context, NodeId::root(), None, HTML_NAMESPACE);
+    let Some(context_node) = main_document.get().get_node_by_id(context_node_id).cloned() else {
+        return false;
+    };
+
+    let document = DocumentBuilder::new_document_fragment(&context_node);
+    matches!(
+        Html5Parser::parse_fragment(&mut chars, document, &context_node, None),
+        Ok(errors) if errors.is_empty()
+    )
+}
+
+/// Returns the comment a processing instruction ends up as in the tree, just like the bogus
+/// comment it would be in HTML mode (ie: `?xml version="1.0"?`)
+fn processing_instruction_comment(target: &str, data: &str) -> String {
+    if data.is_empty() {
+        format!("?{target}?")
+    } else {
+        format!("?{target} {data}?")
+    }
+}
+
 /// The main parser object
 pub struct Html5Parser<'chars> {
     /// tokenizer object
@@ -191,6 +260,10 @@ pub struct Html5Parser<'chars> {
     active_formatting_elements: Vec<ActiveElement>,
     /// Is the current parsing a fragment case. If so, the context_node_id and context_doc should be set as well.
     is_fragment_case: bool,
+    /// Insert a DOCTYPE as a comment when parsing a fragment
+    fragment_doctype_as_comment: bool,
+    /// Custom elements that are built like void elements
+    custom_void_elements: Vec<String>,
     /// A reference to the document we are parsing
     document: DocumentHandle,
     /// Error logger, which is shared with the tokenizer
@@ -203,6 +276,10 @@ pub struct Html5Parser<'chars> {
     insertion_point: Option<usize>,
     /// Ignore when next token is LF
     ignore_lf: bool,
+    /// Maximum number of open elements, see `Html5ParserOptions::max_tree_depth`
+    max_tree_depth: usize,
+    /// True once the maximum tree depth has been reached
+    tree_depth_exceeded: bool,
     /// Sometimes tokens needs to be split up (and it seems the tokenizer cannot do this?)
     token_queue: Vec<Token>,
     /// When true, the parser is finished and should not consume more tokens (there aren't any)
@@ -211,6 +288,8 @@ pub struct Html5Parser<'chars> {
     context_node_id: Option<NodeId>,
     /// Context node document for fragment parsing (we don't want to keep Option<Node> as this clones a whole node
     context_doc: Option<DocumentHandle>,
+    /// Tree builder the inserted nodes are forwarded to, see `parse_document_with_sink()`
+    tree_sink: Option<TreeBuilderSink<'chars>>,
 }
 
 /// Defines the scopes for in_scope()
@@ -232,11 +311,14 @@ enum DispatcherMode {
 impl<'chars> Html5Parser<'chars> {
     // Initializes the parser for whole document parsing
     fn init(
-        tokenizer: Tokenizer<'chars>,
+        mut tokenizer: Tokenizer<'chars>,
         document: DocumentHandle,
         error_logger: Rc<RefCell<ErrorLogger>>,
         options: Option<Html5ParserOptions>,
     ) -> Self {
+        let options = options.unwrap_or_default();
+        tokenizer.record_rcdata_source = options.preserve_rcdata_source;
+
         Self {
             tokenizer,
             insertion_mode: InsertionMode::Initial,
@@ -248,7 +330,7 @@ impl<'chars> Html5Parser<'chars> {
             open_elements: Vec::new(),
             head_element: None,
             form_element: None,
-            scripting_enabled: options.unwrap_or_default().scripting_enabled,
+            scripting_enabled: options.scripting_enabled,
             frameset_ok: true,
             foster_parenting: false,
             script_already_started: false,
@@ -256,16 +338,21 @@ impl<'chars> Html5Parser<'chars> {
             ack_self_closing: false,
             active_formatting_elements: vec![],
             is_fragment_case: false,
+            fragment_doctype_as_comment: options.fragment_doctype_as_comment,
+            custom_void_elements: options.custom_void_elements,
             document,
             error_logger,
             script_nesting_level: 0,
             parser_pause_flag: false,
             insertion_point: None,
             ignore_lf: false,
+            max_tree_depth: options.max_tree_depth.max(MIN_TREE_DEPTH),
+            tree_depth_exceeded: false,
             token_queue: vec![],
             parser_finished: false,
             context_node_id: None,
             context_doc: None,
+            tree_sink: None,
         }
     }
 
@@ -295,16 +382,21 @@ impl<'chars> Html5Parser<'chars> {
             ack_self_closing: false,
             active_formatting_elements: vec![],
             is_fragment_case: false,
+            fragment_doctype_as_comment: false,
+            custom_void_elements: vec![],
             document: Document::clone(&doc),
             error_logger,
             script_nesting_level: 0,
             parser_pause_flag: false,
             insertion_point: None,
             ignore_lf: false,
+            max_tree_depth: DEFAULT_MAX_TREE_DEPTH,
+            tree_depth_exceeded: false,
             token_queue: vec![],
             parser_finished: false,
             context_node_id: None,
             context_doc: None,
+            tree_sink: None,
         }
     }
 
@@ -354,7 +446,11 @@ impl<'chars> Html5Parser<'chars> {
         // @Todo: this does not do anything yet
         let _token = match &context_node.data {
             NodeData::Element(element) => {
-                let node_attributes = element.attributes.clone();
+                let node_attributes = element
+                    .attributes
+                    .iter()
+                    .map(|(name, value)| Attribute::new(name, value))
+                    .collect();
 
                 Token::StartTag {
                     name: context_node.name.clone(),
@@ -395,14 +491,48 @@ impl<'chars> Html5Parser<'chars> {
         chars: &mut CharIterator,
         document: DocumentHandle,
         options: Option<Html5ParserOptions>,
-    ) -> Result<Vec<ParseError>> {
+    ) -> Result<ParseResult> {
         // Create a new error logger that will be used in both the tokenizer and the parser
         let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
 
         let tokenizer = Tokenizer::new(chars, None, error_logger.clone());
-        let mut parser = Html5Parser::init(tokenizer, document, error_logger, options);
+        let mut parser =
+            Html5Parser::init(tokenizer, Document::clone(&document), error_logger, options);
 
-        parser.do_parse()
+        let errors = parser.do_parse()?;
+
+        Ok(ParseResult { document, errors })
+    }
+
+    /// Parses the input chars into a document, just like `parse_document()`, and forwards every
+    /// node to the given tree builder as soon as it is inserted into the document. This lets a
+    /// consumer process the elements as a stream while parsing (ie: through a
+    /// `DocumentTaskSender`) instead of waiting for the whole document.
+    ///
+    /// Tree construction still builds the document, as it reads and moves the nodes it has
+    /// created. The tree builder only receives the insertions, so nodes that are moved or removed
+    /// later on (ie: by the adoption agency algorithm) are not updated, and text and comments are
+    /// always appended to their parent. Doctypes and the contents of templates are not forwarded.
+    /// Returns the first error of the tree builder, after the whole document has been parsed.
+    pub fn parse_document_with_sink(
+        chars: &'chars mut CharIterator,
+        document: DocumentHandle,
+        sink: &'chars mut dyn TreeBuilder,
+        options: Option<Html5ParserOptions>,
+    ) -> Result<ParseResult> {
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+
+        let tokenizer = Tokenizer::new(chars, None, error_logger.clone());
+        let mut parser =
+            Html5Parser::init(tokenizer, Document::clone(&document), error_logger, options);
+        parser.tree_sink = Some(TreeBuilderSink::new(sink));
+
+        let errors = parser.do_parse()?;
+        if let Some(sink) = parser.tree_sink.take() {
+            sink.finish()?;
+        }
+
+        Ok(ParseResult { document, errors })
     }
 
     /// Internal parser function that does the actual parsing
@@ -419,6 +549,16 @@ impl<'chars> Html5Parser<'chars> {
             if !self.reprocess_token {
                 self.current_token = self.fetch_next_token();
 
+                // Tree construction has no notion of processing instructions, so they end up as a
+                // comment, just like the bogus comment they would be in HTML mode. This is the only
+                // place where tokens enter tree construction, so no insertion mode sees them.
+                if let Token::ProcessingInstruction { target, data } = &self.current_token {
+                    self.current_token = Token::Comment {
+                        value: processing_instruction_comment(target, data),
+                        closing: CommentClosing::Abrupt,
+                    };
+                }
+
                 // If we reprocess a given token, the dispatcher mode should stay the same and
                 // should not be re-evaluated
                 dispatcher_mode = self.select_dispatch_mode();
@@ -436,6 +576,23 @@ impl<'chars> Html5Parser<'chars> {
                 }
             }
 
+            // Only void elements and elements in foreign content acknowledge a self-closing flag.
+            // On other elements it is ignored, so the element stays open.
+            if !self.reprocess_token {
+                if let Token::StartTag {
+                    is_self_closing: true,
+                    ..
+                } = self.current_token
+                {
+                    if !self.ack_self_closing {
+                        self.parse_error(
+                            ParserError::NonVoidHtmlElementStartTagWithTrailingSolidus.as_str(),
+                        );
+                    }
+                }
+                self.ack_self_closing = false;
+            }
+
             #[cfg(feature = "debug_parser")]
             self.display_debug_info();
         }
@@ -466,7 +623,10 @@ impl<'chars> Html5Parser<'chars> {
 
                 self.frameset_ok = false;
             }
-            Token::Comment(..) => {
+            Token::ProcessingInstruction { .. } => {
+                unreachable!("processing instructions are converted to comments");
+            }
+            Token::Comment { .. } => {
                 self.insert_comment_element(&self.current_token.clone(), None);
             }
             Token::DocType { .. } => {
@@ -524,9 +684,9 @@ impl<'chars> Html5Parser<'chars> {
             Token::StartTag {
                 name, attributes, ..
             } if name == "font"
-                && (attributes.contains_key("color")
-                    || attributes.contains_key("face")
-                    || attributes.contains_key("size")) =>
+                && attributes
+                    .iter()
+                    .any(|attr| matches!(attr.name.as_str(), "color" | "face" | "size")) =>
             {
                 self.process_unexpected_html_tag();
             }
@@ -580,7 +740,7 @@ impl<'chars> Html5Parser<'chars> {
                 let mut node_idx = self.open_elements.len() - 1;
                 let mut node = get_node_by_id!(self.document, self.open_elements[node_idx]);
 
-                if node.name.to_lowercase() != *name {
+                if !ascii_case_insensitive_eq(&node.name, name) {
                     self.parse_error("end tag does not match current node");
                 }
 
@@ -592,7 +752,7 @@ impl<'chars> Html5Parser<'chars> {
                         _ => {}
                     }
 
-                    if node.name.to_lowercase() == *name {
+                    if ascii_case_insensitive_eq(&node.name, name) {
                         while let Some(node_id) = self.open_elements.pop() {
                             if node_id == node.id {
                                 break;
@@ -661,7 +821,10 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         // ignore token
                     }
-                    Token::Comment(..) => {
+                    Token::ProcessingInstruction { .. } => {
+                        unreachable!("processing instructions are converted to comments");
+                    }
+                    Token::Comment { .. } => {
                         self.insert_comment_element(
                             &self.current_token.clone(),
                             Some(NodeId::root()),
@@ -731,7 +894,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::DocType { .. } => {
                         self.parse_error("doctype not allowed in before html insertion mode");
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.insert_comment_element(
                             &self.current_token.clone(),
                             Some(NodeId::root()),
@@ -767,7 +930,7 @@ impl<'chars> Html5Parser<'chars> {
                     let token = Token::StartTag {
                         name: "html".to_string(),
                         is_self_closing: false,
-                        attributes: HashMap::new(),
+                        attributes: vec![],
                     };
                     self.insert_document_element(&token);
 
@@ -787,7 +950,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         // ignore token
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.insert_comment_element(&self.current_token.clone(), None);
                     }
                     Token::DocType { .. } => {
@@ -819,7 +982,7 @@ impl<'chars> Html5Parser<'chars> {
                     let token = Token::StartTag {
                         name: "head".to_string(),
                         is_self_closing: false,
-                        attributes: HashMap::new(),
+                        attributes: vec![],
                     };
                     let node_id = self.insert_html_element(&token);
                     self.head_element = Some(node_id);
@@ -853,7 +1016,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         self.handle_in_head();
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.handle_in_head();
                     }
                     Token::StartTag { name, .. }
@@ -905,7 +1068,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         self.insert_text_element(&self.current_token.clone());
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.insert_comment_element(&self.current_token.clone(), None);
                     }
                     Token::DocType { .. } => {
@@ -976,7 +1139,7 @@ impl<'chars> Html5Parser<'chars> {
                     let token = Token::StartTag {
                         name: "body".to_string(),
                         is_self_closing: false,
-                        attributes: HashMap::new(),
+                        attributes: vec![],
                     };
                     self.insert_html_element(&token);
 
@@ -989,6 +1152,9 @@ impl<'chars> Html5Parser<'chars> {
                 match &self.current_token {
                     Token::Text(..) => {
                         self.insert_text_element(&self.current_token.clone());
+                        if let Some(source) = self.tokenizer.take_rcdata_source() {
+                            self.insert_text_source(&source);
+                        }
                     }
                     Token::Eof => {
                         self.parse_error("eof not allowed in text insertion mode");
@@ -1153,7 +1319,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         self.insert_text_element(&self.current_token.clone());
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.insert_comment_element(&self.current_token.clone(), None);
                     }
                     Token::DocType { .. } => {
@@ -1228,7 +1394,7 @@ impl<'chars> Html5Parser<'chars> {
                         let token = Token::StartTag {
                             name: "tr".to_string(),
                             is_self_closing: false,
-                            attributes: HashMap::new(),
+                            attributes: vec![],
                         };
                         self.insert_html_element(&token);
 
@@ -1510,7 +1676,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         self.handle_in_body();
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         let html_node_id = self.open_elements.first().unwrap_or_default();
                         self.insert_comment_element(
                             &self.current_token.clone(),
@@ -1554,7 +1720,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         self.insert_text_element(&self.current_token.clone());
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.insert_comment_element(&self.current_token.clone(), None);
                     }
                     Token::DocType { .. } => {
@@ -1616,7 +1782,7 @@ impl<'chars> Html5Parser<'chars> {
                     Token::Text(..) if self.current_token.is_empty_or_white() => {
                         self.insert_text_element(&self.current_token.clone());
                     }
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.insert_comment_element(&self.current_token.clone(), None);
                     }
                     Token::DocType { .. } => {
@@ -1644,7 +1810,7 @@ impl<'chars> Html5Parser<'chars> {
                 }
             }
             InsertionMode::AfterAfterBody => match &self.current_token {
-                Token::Comment(..) => {
+                Token::Comment { .. } => {
                     self.insert_comment_element(&self.current_token.clone(), Some(NodeId::root()));
                 }
                 Token::DocType { .. } => {
@@ -1673,7 +1839,7 @@ impl<'chars> Html5Parser<'chars> {
             },
             InsertionMode::AfterAfterFrameset => {
                 match &self.current_token {
-                    Token::Comment(..) => {
+                    Token::Comment { .. } => {
                         self.insert_comment_element(
                             &self.current_token.clone(),
                             Some(NodeId::root()),
@@ -1840,13 +2006,17 @@ impl<'chars> Html5Parser<'chars> {
                 &pub_identifier.clone().unwrap_or_default(),
                 &sys_identifier.clone().unwrap_or_default(),
             ),
-            Token::StartTag {
-                name, attributes, ..
-            } => Node::new_element(&self.document, name, attributes.clone(), namespace),
+            Token::StartTag { name, .. } => {
+                Node::new_element(&self.document, name, token.attr_map(), namespace)
+            }
             Token::EndTag { name, .. } => {
                 Node::new_element(&self.document, name, HashMap::new(), namespace)
             }
-            Token::Comment(value) => Node::new_comment(&self.document, value),
+            Token::Comment { value, .. } => Node::new_comment(&self.document, value),
+            Token::ProcessingInstruction { target, data } => Node::new_comment(
+                &self.document,
+                &processing_instruction_comment(target, data),
+            ),
             Token::Text(value) => Node::new_text(&self.document, value.to_string().as_str()),
             Token::Eof => {
                 panic!("EOF token not allowed");
@@ -2042,8 +2212,10 @@ impl<'chars> Html5Parser<'chars> {
 
     /// Checks if the given element is in given scope
     fn is_in_scope(&self, tag: &str, namespace: &str, scope: Scope) -> bool {
+        // Borrow the nodes instead of cloning them, as this walks the whole stack for most tags
+        let doc = self.document.get();
         for &node_id in self.open_elements.iter().rev() {
-            let node = get_node_by_id!(self.document, node_id).clone();
+            let node = doc.get_node_by_id(node_id).expect("Node not found");
             if node.name == tag && node.is_namespace(namespace) {
                 return true;
             }
@@ -2144,7 +2316,10 @@ impl<'chars> Html5Parser<'chars> {
                     self.frameset_ok = false;
                 }
             }
-            Token::Comment(..) => {
+            Token::ProcessingInstruction { .. } => {
+                unreachable!("processing instructions are converted to comments");
+            }
+            Token::Comment { .. } => {
                 self.insert_comment_element(&self.current_token.clone(), None);
             }
             Token::DocType { .. } => {
@@ -2168,9 +2343,11 @@ impl<'chars> Html5Parser<'chars> {
                     .get_node_by_id_mut(first_node_id)
                     .expect("node not found");
                 if let NodeData::Element(element) = &mut first_node.data {
-                    for (key, value) in attributes {
-                        if !element.attributes.contains_key(key) {
-                            element.attributes.insert(key.to_owned(), value.to_owned());
+                    for attr in attributes {
+                        if !element.attributes.contains_key(&attr.name) {
+                            element
+                                .attributes
+                                .insert(attr.name.clone(), attr.value.clone());
                         }
                     }
                 };
@@ -2220,9 +2397,11 @@ impl<'chars> Html5Parser<'chars> {
                         .expect("node not found");
 
                     if let NodeData::Element(element) = &mut body_node.data {
-                        for (key, value) in attributes {
-                            if !element.attributes.contains_key(key) {
-                                element.attributes.insert(key.to_owned(), value.to_owned());
+                        for attr in attributes {
+                            if !element.attributes.contains_key(&attr.name) {
+                                element
+                                    .attributes
+                                    .insert(attr.name.clone(), attr.value.clone());
                             }
                         }
                     }
@@ -2546,7 +2725,7 @@ impl<'chars> Html5Parser<'chars> {
                     let token = Token::StartTag {
                         name: "p".to_string(),
                         is_self_closing: false,
-                        attributes: HashMap::new(),
+                        attributes: vec![],
                     };
                     self.insert_html_element(&token);
                 }
@@ -2762,6 +2941,7 @@ impl<'chars> Html5Parser<'chars> {
                 name,
                 is_self_closing,
                 attributes,
+                ..
             } if name == "input" => {
                 self.reconstruct_formatting();
 
@@ -2770,8 +2950,10 @@ impl<'chars> Html5Parser<'chars> {
 
                 self.acknowledge_closing_tag(*is_self_closing);
 
-                if !attributes.contains_key("type")
-                    || attributes.get("type").unwrap().to_lowercase() != *"hidden"
+                if !attributes
+                    .iter()
+                    .find(|attr| attr.name == "type")
+                    .is_some_and(|attr| ascii_case_insensitive_eq(&attr.value, "hidden"))
                 {
                     self.frameset_ok = false;
                 }
@@ -2952,6 +3134,18 @@ impl<'chars> Html5Parser<'chars> {
                 self.parse_error("tag not allowed in in body insertion mode");
                 // ignore token
             }
+            Token::StartTag {
+                name,
+                is_self_closing,
+                ..
+            } if self.custom_void_elements.contains(name) => {
+                self.reconstruct_formatting();
+
+                self.insert_html_element(&self.current_token.clone());
+                self.open_elements.pop();
+
+                self.acknowledge_closing_tag(*is_self_closing);
+            }
             Token::StartTag { .. } => {
                 self.reconstruct_formatting();
                 self.insert_html_element(&self.current_token.clone());
@@ -2975,7 +3169,7 @@ impl<'chars> Html5Parser<'chars> {
             Token::Text(..) if self.current_token.is_empty_or_white() => {
                 self.insert_text_element(&self.current_token.clone());
             }
-            Token::Comment(..) => {
+            Token::Comment { .. } => {
                 self.insert_comment_element(&self.current_token.clone(), None);
             }
             Token::DocType { .. } => {
@@ -3022,6 +3216,7 @@ impl<'chars> Html5Parser<'chars> {
                 self.insertion_mode = InsertionMode::InHeadNoscript;
             }
             Token::StartTag { name, .. } if name == "script" => {
+                self.limit_tree_depth();
                 let insert_position = self.appropriate_place_insert(None);
                 let node = self.create_node(&self.current_token.clone(), HTML_NAMESPACE);
                 let node_id = self.document.get_mut().add_new_node(node);
@@ -3121,7 +3316,10 @@ impl<'chars> Html5Parser<'chars> {
     /// Handle insertion mode "in_template"
     fn handle_in_template(&mut self) {
         match &self.current_token {
-            Token::Text(..) | Token::Comment(..) | Token::DocType { .. } => {
+            Token::ProcessingInstruction { .. } => {
+                unreachable!("processing instructions are converted to comments");
+            }
+            Token::Text(..) | Token::Comment { .. } | Token::DocType { .. } => {
                 self.handle_in_body();
             }
             Token::StartTag { name, .. }
@@ -3217,7 +3415,7 @@ impl<'chars> Html5Parser<'chars> {
                 self.insertion_mode = InsertionMode::InTableText;
                 self.reprocess_token = true;
             }
-            Token::Comment(..) => {
+            Token::Comment { .. } => {
                 self.insert_comment_element(&self.current_token.clone(), None);
             }
             Token::DocType { .. } => {
@@ -3241,7 +3439,7 @@ impl<'chars> Html5Parser<'chars> {
                 let token = Token::StartTag {
                     name: "colgroup".to_string(),
                     is_self_closing: false,
-                    attributes: HashMap::new(),
+                    attributes: vec![],
                 };
                 self.insert_html_element(&token);
 
@@ -3263,7 +3461,7 @@ impl<'chars> Html5Parser<'chars> {
                 let token = Token::StartTag {
                     name: "tbody".to_string(),
                     is_self_closing: false,
-                    attributes: HashMap::new(),
+                    attributes: vec![],
                 };
                 self.insert_html_element(&token);
 
@@ -3321,9 +3519,12 @@ impl<'chars> Html5Parser<'chars> {
                 name,
                 is_self_closing,
                 attributes,
+                ..
             } if name == "input" => {
-                if !attributes.contains_key("type")
-                    || attributes.get("type").unwrap().to_lowercase() != *"hidden"
+                if !attributes
+                    .iter()
+                    .find(|attr| attr.name == "type")
+                    .is_some_and(|attr| ascii_case_insensitive_eq(&attr.value, "hidden"))
                 {
                     anything_else = true;
                 } else {
@@ -3377,7 +3578,7 @@ impl<'chars> Html5Parser<'chars> {
             Token::Text(..) => {
                 self.insert_text_element(&self.current_token.clone());
             }
-            Token::Comment(..) => {
+            Token::Comment { .. } => {
                 self.insert_comment_element(&self.current_token.clone(), None);
             }
             Token::DocType { .. } => {
@@ -3664,18 +3865,11 @@ impl<'chars> Html5Parser<'chars> {
     /// Adjusts attributes names in the given token for SVG
     fn adjust_svg_attributes(&self, token: &mut Token) {
         if let Token::StartTag { attributes, .. } = token {
-            let mut new_attributes = HashMap::new();
-            for (name, value) in attributes.iter() {
-                if SVG_ADJUSTMENTS_ATTRIBUTES.contains_key(name) {
-                    let &new_name = SVG_ADJUSTMENTS_ATTRIBUTES
-                        .get(name)
-                        .expect("svg adjustments");
-                    new_attributes.insert(new_name.to_owned(), value.clone());
-                } else {
-                    new_attributes.insert(name.clone(), value.clone());
+            for attr in attributes.iter_mut() {
+                if let Some(&new_name) = SVG_ADJUSTMENTS_ATTRIBUTES.get(&attr.name) {
+                    attr.name = new_name.to_owned();
                 }
             }
-            *attributes = new_attributes;
         }
     }
 
@@ -3691,32 +3885,21 @@ impl<'chars> Html5Parser<'chars> {
     // Adjust attribute names in the given token for MathML
     fn adjust_mathml_attributes(&self, token: &mut Token) {
         if let Token::StartTag { attributes, .. } = token {
-            let mut new_attributes = HashMap::new();
-            for (name, value) in attributes.iter() {
-                if MATHML_ADJUSTMENTS.contains_key(name) {
-                    let &new_name = MATHML_ADJUSTMENTS.get(name).expect("svg adjustments");
-                    new_attributes.insert(new_name.to_owned(), value.clone());
-                } else {
-                    new_attributes.insert(name.clone(), value.clone());
+            for attr in attributes.iter_mut() {
+                if let Some(&new_name) = MATHML_ADJUSTMENTS.get(&attr.name) {
+                    attr.name = new_name.to_owned();
                 }
             }
-            *attributes = new_attributes;
         }
     }
 
     fn adjust_foreign_attributes(&self, token: &mut Token) {
         if let Token::StartTag { attributes, .. } = token {
-            let mut new_attributes = HashMap::new();
-            for (name, value) in attributes.iter() {
-                if XML_ADJUSTMENTS.contains_key(name) {
-                    let (prefix, local_name, _namespace) =
-                        XML_ADJUSTMENTS.get(name).expect("cml adjustments");
-                    new_attributes.insert(format!("{prefix} {local_name}"), value.clone());
-                } else {
-                    new_attributes.insert(name.clone(), value.clone());
+            for attr in attributes.iter_mut() {
+                if let Some((prefix, local_name, _namespace)) = XML_ADJUSTMENTS.get(&attr.name) {
+                    attr.name = format!("{prefix} {local_name}");
                 }
             }
-            *attributes = new_attributes;
         }
     }
 
@@ -3814,20 +3997,22 @@ impl<'chars> Html5Parser<'chars> {
     }
 
     fn parser_data(&self) -> ParserData {
-        if self.open_elements.is_empty() {
-            return ParserData {
-                adjusted_node_namespace: HTML_NAMESPACE.to_string(),
-            };
+        ParserData {
+            adjusted_node_namespace: self.current_namespace().as_str().to_string(),
         }
+    }
 
-        let namespace = self
-            .get_adjusted_current_node()
-            .namespace
-            .expect("namespace not found");
-
-        ParserData {
-            adjusted_node_namespace: namespace,
+    /// Returns the namespace of the adjusted current node. As every element on the stack of open
+    /// elements carries its own namespace, pushing and popping elements keeps this up to date.
+    /// When the stack is empty, we are in HTML content.
+    fn current_namespace(&self) -> Namespace {
+        if self.open_elements.is_empty() {
+            return Namespace::Html;
         }
+
+        self.get_adjusted_current_node()
+            .get_namespace()
+            .expect("namespace not found")
     }
 
     /// Fetches the next token from the tokenizer. However, if the token is a text token AND
@@ -3846,6 +4031,32 @@ impl<'chars> Html5Parser<'chars> {
                 // for c in value.chars() {
                 //     self.token_queue.push(Token::Text(c.to_string()));
                 // }
+            } else if self.is_fragment_case && self.fragment_doctype_as_comment {
+                if let Token::DocType {
+                    name,
+                    pub_identifier,
+                    sys_identifier,
+                    ..
+                } = &token
+                {
+                    self.parse_error("doctype not allowed in fragment");
+
+                    let mut value = format!("DOCTYPE {}", name.as_deref().unwrap_or_default());
+                    if let Some(pub_id) = pub_identifier {
+                        value.push_str(&format!(r#" PUBLIC "{pub_id}""#));
+                    }
+                    if let Some(sys_id) = sys_identifier {
+                        match pub_identifier {
+                            Some(_) => value.push_str(&format!(r#" "{sys_id}""#)),
+                            None => value.push_str(&format!(r#" SYSTEM "{sys_id}""#)),
+                        }
+                    }
+                    return Token::Comment {
+                        value,
+                        closing: CommentClosing::Abrupt,
+                    };
+                }
+                return token;
             } else {
                 // Simply return the token
                 return token;
@@ -4062,6 +4273,8 @@ mod test {
     use super::*;
     use crate::bytes::Encoding;
     use crate::html5::parser::document::DocumentBuilder;
+    use crate::html5::serialize::{serialize_document, SerializeOpts};
+    use crate::html5::tokenizer::ParseMode;
 
     macro_rules! node_create {
         ($self:expr, $name:expr) => {{
@@ -4291,6 +4504,26 @@ mod test {
         assert!(parser.is_in_scope("select", HTML_NAMESPACE, Scope::Select));
     }
 
+    #[test]
+    fn parse_result() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p>&bad;", Some(Encoding::UTF8));
+
+        let result =
+            Html5Parser::parse_document(&mut chars, DocumentBuilder::new_document(), None).unwrap();
+
+        assert!(result
+            .errors
+            .iter()
+            .any(|e| e.message == "unknown-named-character-reference"));
+
+        let binding = result.document.get();
+        let p = binding.get_node_by_id(NodeId::from(4)).unwrap();
+        assert_eq!(p.name, "p");
+        let text = binding.get_node_by_id(p.children[0]).unwrap();
+        assert!(matches!(&text.data, NodeData::Text(t) if t.value == "&bad;"));
+    }
+
     #[test]
     fn reconstruct_formatting() {
         let mut chars = CharIterator::new();
@@ -4333,6 +4566,547 @@ mod test {
         assert!(element.classes.is_active("three"));
     }
 
+    #[test]
+    fn well_formed_fragment() {
+        assert!(is_well_formed_fragment("<b>x</b>", "div"));
+        assert!(is_well_formed_fragment("a <i>b</i> c", "p"));
+        assert!(is_well_formed_fragment("", "div"));
+        assert!(is_well_formed_fragment("<td>x</td>", "tr"));
+
+        assert!(!is_well_formed_fragment("<b>x</i>", "div"));
+        assert!(!is_well_formed_fragment("a &copy b", "div"));
+        assert!(!is_well_formed_fragment("<td>x</td>", "div"));
+    }
+
+    #[test]
+    fn fragment_doctype_as_comment() {
+        for (enabled, expected) in [
+            (false, "└─ <html>\n   └─ <p>\n"),
+            (true, "└─ <html>\n   ├─ <!-- DOCTYPE html -->\n   └─ <p>\n"),
+        ] {
+            let mut chars = CharIterator::new();
+            chars.read_from_str("<!DOCTYPE html><p>", Some(Encoding::UTF8));
+
+            let mut main_document = DocumentBuilder::new_document();
+            let context_node_id =
+                main_document.create_element("div", NodeId::root(), None, HTML_NAMESPACE);
+            let context_node = main_document
+                .get()
+                .get_node_by_id(context_node_id)
+                .cloned()
+                .unwrap();
+
+            let document = DocumentBuilder::new_document_fragment(&context_node);
+            let options = Html5ParserOptions {
+                fragment_doctype_as_comment: enabled,
+                ..Default::default()
+            };
+            let errors = Html5Parser::parse_fragment(
+                &mut chars,
+                Document::clone(&document),
+                &context_node,
+                Some(options),
+            )
+            .unwrap();
+
+            // The doctype is a parse error in both modes
+            assert_eq!(errors.len(), 1, "enabled: {enabled}");
+            assert_eq!(document.to_string(), expected, "enabled: {enabled}");
+        }
+    }
+
+    #[test]
+    fn select_options_are_siblings() {
+        for input in [
+            "<select><option>a<option>b</select>",
+            // In a table cell the select is parsed in the "in select in table" mode
+            "<table><tr><td><select><option>a<option>b</select></td></tr></table>",
+        ] {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(input, Some(Encoding::UTF8));
+
+            let document = DocumentBuilder::new_document();
+            let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+            let binding = document.get();
+            let select = binding.get_elements_by_tag_name("select")[0];
+
+            // The second option closes the first one, instead of being nested in it
+            assert_eq!(select.children.len(), 2, "for {input:?}");
+            for (child, text) in select.children.iter().zip(["a", "b"]) {
+                let option = binding.get_node_by_id(*child).unwrap();
+                assert_eq!(option.name, "option");
+                assert_eq!(option.parent, Some(select.id));
+                assert_eq!(option.children.len(), 1);
+
+                let text_node = binding.get_node_by_id(option.children[0]).unwrap();
+                assert!(matches!(&text_node.data, NodeData::Text(t) if t.value == text));
+            }
+        }
+    }
+
+    #[test]
+    fn leading_newline_is_ignored() {
+        for (input, name, expected) in [
+            ("<textarea>\nhello</textarea>", "textarea", "hello"),
+            ("<textarea>\n\nhello", "textarea", "\nhello"),
+            ("<pre>\nhello</pre>", "pre", "hello"),
+            ("<listing>\n\nhello</listing>", "listing", "\nhello"),
+        ] {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(input, Some(Encoding::UTF8));
+
+            let document = DocumentBuilder::new_document();
+            let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+            let binding = document.get();
+            let element = binding.get_elements_by_tag_name(name)[0];
+
+            let text = element
+                .children
+                .iter()
+                .filter_map(
+                    |child| match &binding.get_node_by_id(*child).unwrap().data {
+                        NodeData::Text(text) => Some(text.value.clone()),
+                        _ => None,
+                    },
+                )
+                .collect::<String>();
+            assert_eq!(text, expected, "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn adjacent_text_is_merged() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p>a&amp;b", Some(Encoding::UTF8));
+
+        let document = DocumentBuilder::new_document();
+        let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+        let binding = document.get();
+        let p = binding.get_elements_by_tag_name("p")[0];
+
+        assert_eq!(p.children.len(), 1);
+        let text = binding.get_node_by_id(p.children[0]).unwrap();
+        assert!(matches!(&text.data, NodeData::Text(t) if t.value == "a&b"));
+    }
+
+    #[test]
+    fn attribute_whitespace_is_preserved() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<div title=\"  a  b  \" data-x=\"\tc\n\">",
+            Some(Encoding::UTF8),
+        );
+
+        let document = DocumentBuilder::new_document();
+        let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+        let binding = document.get();
+        let div = binding.get_elements_by_tag_name("div")[0];
+        let NodeData::Element(element) = &div.data else {
+            panic!("expected an element");
+        };
+        assert_eq!(element.attributes.get("title").unwrap(), "  a  b  ");
+        assert_eq!(element.attributes.get("data-x").unwrap(), "\tc\n");
+    }
+
+    #[test]
+    fn raw_text_elements_have_a_single_text_child() {
+        for (input, name, expected) in [
+            ("<style>body{}</style>", "style", "body{}"),
+            (
+                "<style>a > b { x: '</p>' }</style>",
+                "style",
+                "a > b { x: '</p>' }",
+            ),
+            ("<title>a &amp; <b>b</b></title>", "title", "a & <b>b</b>"),
+            ("<textarea>a<br>b</textarea>", "textarea", "a<br>b"),
+            ("<script>if (a<b) {}</script>", "script", "if (a<b) {}"),
+        ] {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(input, Some(Encoding::UTF8));
+
+            let document = DocumentBuilder::new_document();
+            let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+            let binding = document.get();
+            let element = binding.get_elements_by_tag_name(name)[0];
+            assert_eq!(element.children.len(), 1, "for {input:?}");
+
+            let text = binding.get_node_by_id(element.children[0]).unwrap();
+            assert!(
+                matches!(&text.data, NodeData::Text(t) if t.value == expected),
+                "for {input:?}: {:?}",
+                text.data
+            );
+
+            // The element is closed by its end tag, so nothing else ends up in it
+            assert_eq!(
+                binding.get_elements_by_tag_name("b").len(),
+                0,
+                "for {input:?}"
+            );
+        }
+    }
+
+    fn check_max_tree_depth(count: usize) {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(&"<div>".repeat(count), Some(Encoding::UTF8));
+
+        let document = DocumentBuilder::new_document();
+        let options = Html5ParserOptions {
+            max_tree_depth: 32,
+            ..Default::default()
+        };
+        let result =
+            Html5Parser::parse_document(&mut chars, Document::clone(&document), Some(options))
+                .unwrap();
+
+        // The error is only reported once
+        let depth_errors = result
+            .errors
+            .iter()
+            .filter(|e| e.message == "maximum tree depth exceeded")
+            .count();
+        assert_eq!(depth_errors, 1);
+
+        let binding = document.get();
+        let divs = binding.get_elements_by_tag_name("div");
+        assert_eq!(divs.len(), count);
+
+        // Divs beyond the maximum depth end up next to each other instead of nested
+        let deepest = divs.iter().map(|div| node_depth(&binding, div.id)).max();
+        assert_eq!(deepest, Some(32));
+        assert_eq!(node_depth(&binding, divs[divs.len() - 1].id), 32);
+        drop(binding);
+
+        let html = serialize_document(&document, &SerializeOpts::default());
+        assert_eq!(html.matches("<div>").count(), count);
+    }
+
+    /// Returns the number of ancestors of the given node
+    fn node_depth(document: &Document, node_id: NodeId) -> usize {
+        let mut depth = 0;
+        let mut node = document.get_node_by_id(node_id).unwrap();
+        while let Some(parent) = node.parent {
+            node = document.get_node_by_id(parent).unwrap();
+            depth += 1;
+        }
+        depth
+    }
+
+    #[test]
+    fn max_tree_depth() {
+        check_max_tree_depth(5_000);
+    }
+
+    #[test]
+    #[ignore = "stress test, slow in debug builds"]
+    fn max_tree_depth_stress() {
+        check_max_tree_depth(100_000);
+    }
+
+    #[test]
+    fn max_tree_depth_small_limits() {
+        for max_tree_depth in 0..=5 {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(
+                "<div><div>x</div></div><template><p><b>y</b></p></template>\
+                <table><tr><td><i><u>z</u></i></td></tr></table>",
+                Some(Encoding::UTF8),
+            );
+
+            let document = DocumentBuilder::new_document();
+            let options = Html5ParserOptions {
+                max_tree_depth,
+                ..Default::default()
+            };
+            let _ =
+                Html5Parser::parse_document(&mut chars, Document::clone(&document), Some(options));
+
+            let binding = document.get();
+            let name_of = |node_id: &NodeId| binding.get_node_by_id(*node_id).unwrap().name.clone();
+
+            // The document structure itself is never flattened
+            let root = binding.get_root();
+            assert_eq!(
+                root.children.iter().map(name_of).collect::<Vec<_>>(),
+                ["html"]
+            );
+            let html = binding.get_node_by_id(root.children[0]).unwrap();
+            assert_eq!(
+                html.children.iter().map(name_of).collect::<Vec<_>>(),
+                ["head", "body"],
+                "for depth {max_tree_depth}"
+            );
+
+            // Neither are templates and tables
+            let is_inside = |node_id: NodeId, ancestor: &str| {
+                let mut node = binding.get_node_by_id(node_id).unwrap();
+                while let Some(parent) = node.parent {
+                    node = binding.get_node_by_id(parent).unwrap();
+                    if node.name == ancestor {
+                        return true;
+                    }
+                }
+                false
+            };
+            let p = binding.get_elements_by_tag_name("p")[0].id;
+            assert!(is_inside(p, "template"), "for depth {max_tree_depth}");
+            let i = binding.get_elements_by_tag_name("i")[0].id;
+            assert!(is_inside(i, "td"), "for depth {max_tree_depth}");
+            let u = binding.get_elements_by_tag_name("u")[0].id;
+            assert!(is_inside(u, "td"), "for depth {max_tree_depth}");
+        }
+    }
+
+    #[test]
+    fn comments_before_html_element() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<!-- c -->\n<html> <!-- d --><head></head></html>",
+            Some(Encoding::UTF8),
+        );
+
+        let document = DocumentBuilder::new_document();
+        let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+        // Comments before <html> are children of the document, the whitespace between them is
+        // ignored. Once the html element exists, comments are inserted into it.
+        assert_eq!(
+            document.to_string(),
+            "\
+└─ Document
+   ├─ <!--  c  -->
+   └─ <html>
+      ├─ <!--  d  -->
+      ├─ <head>
+      └─ <body>
+"
+        );
+    }
+
+    #[test]
+    fn svg_namespace() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<svg><circle/><rect/></svg>", Some(Encoding::UTF8));
+
+        let document = DocumentBuilder::new_document();
+        let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+        let binding = document.get();
+
+        // document -> html -> head -> body -> svg -> (circle, rect)
+        let svg = binding.get_node_by_id(4.into()).unwrap();
+        assert_eq!(svg.name, "svg");
+        assert_eq!(svg.get_namespace(), Some(Namespace::Svg));
+        assert_eq!(svg.children.len(), 2);
+
+        // The self-closing circle does not contain the rect
+        let circle = binding.get_node_by_id(svg.children[0]).unwrap();
+        assert_eq!(circle.name, "circle");
+        assert_eq!(circle.get_namespace(), Some(Namespace::Svg));
+        assert!(circle.children.is_empty());
+
+        let rect = binding.get_node_by_id(svg.children[1]).unwrap();
+        assert_eq!(rect.name, "rect");
+        assert_eq!(rect.get_namespace(), Some(Namespace::Svg));
+    }
+
+    #[test]
+    fn self_closing_depends_on_namespace() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<svg><rect/><g/></svg><div/><span>x</span><br/><math><mi/></math>",
+            Some(Encoding::UTF8),
+        );
+
+        let document = DocumentBuilder::new_document();
+        let result =
+            Html5Parser::parse_document(&mut chars, Document::clone(&document), None).unwrap();
+
+        // Any element in foreign content can be self-closed, while <div/> in HTML stays open
+        assert_eq!(
+            document.to_string(),
+            r#"└─ Document
+   └─ <html>
+      ├─ <head>
+      └─ <body>
+         ├─ <svg>
+         │  ├─ <rect>
+         │  └─ <g>
+         └─ <div>
+            ├─ <span>
+            │  └─ "x"
+            ├─ <br>
+            └─ <math>
+               └─ <mi>
+"#
+        );
+
+        // Only the self-closing flag on the div is not acknowledged
+        let solidus_errors = result
+            .errors
+            .iter()
+            .filter(|e| e.message == "non-void-html-element-start-tag-with-trailing-solidus")
+            .count();
+        assert_eq!(solidus_errors, 1);
+    }
+
+    #[test]
+    fn processing_instructions_become_comments() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<?xml version=\"1.0\"?><?php?><p>x</p>",
+            Some(Encoding::UTF8),
+        );
+
+        let mut parser = Html5Parser::new_parser(&mut chars);
+        parser.tokenizer.parse_mode = ParseMode::XmlLike;
+        parser.do_parse().unwrap();
+
+        assert_eq!(
+            parser.document.to_string(),
+            r#"└─ Document
+   ├─ <!-- ?xml version="1.0"? -->
+   ├─ <!-- ?php? -->
+   └─ <html>
+      ├─ <head>
+      └─ <body>
+         └─ <p>
+            └─ "x"
+"#
+        );
+
+        // Reprocessed tokens (after the pending table text) and templates see the comment too
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<table>x<?a?></table><template><?b?></template>",
+            Some(Encoding::UTF8),
+        );
+
+        let mut parser = Html5Parser::new_parser(&mut chars);
+        parser.tokenizer.parse_mode = ParseMode::XmlLike;
+        parser.do_parse().unwrap();
+
+        assert_eq!(
+            parser.document.to_string(),
+            r#"└─ Document
+   └─ <html>
+      ├─ <head>
+      └─ <body>
+         ├─ "x"
+         ├─ <table>
+         │  └─ <!-- ?a? -->
+         └─ <template>
+            └─ <!-- ?b? -->
+"#
+        );
+    }
+
+    #[test]
+    fn custom_void_elements() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<my-icon/>a<my-icon>b<x-box/>c", Some(Encoding::UTF8));
+
+        let document = DocumentBuilder::new_document();
+        let options = Html5ParserOptions {
+            custom_void_elements: vec!["my-icon".into()],
+            ..Default::default()
+        };
+        let result =
+            Html5Parser::parse_document(&mut chars, Document::clone(&document), Some(options))
+                .unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            r#"└─ Document
+   └─ <html>
+      ├─ <head>
+      └─ <body>
+         ├─ <my-icon>
+         ├─ "a"
+         ├─ <my-icon>
+         ├─ "b"
+         └─ <x-box>
+            └─ "c"
+"#
+        );
+
+        // The trailing solidus is only an error on elements that are not registered
+        let solidus_errors = result
+            .errors
+            .iter()
+            .filter(|e| e.message == "non-void-html-element-start-tag-with-trailing-solidus")
+            .count();
+        assert_eq!(solidus_errors, 1);
+    }
+
+    #[test]
+    fn content_after_self_closing_elements() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<p>a<br/>b</p><svg><rect/><circle/>c<g/></svg>d",
+            Some(Encoding::UTF8),
+        );
+
+        let document = DocumentBuilder::new_document();
+        let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+
+        // Self-closed elements are popped right away, so whatever follows becomes a sibling
+        assert_eq!(
+            document.to_string(),
+            r#"└─ Document
+   └─ <html>
+      ├─ <head>
+      └─ <body>
+         ├─ <p>
+         │  ├─ "a"
+         │  ├─ <br>
+         │  └─ "b"
+         ├─ <svg>
+         │  ├─ <rect>
+         │  ├─ <circle>
+         │  ├─ "c"
+         │  └─ <g>
+         └─ "d"
+"#
+        );
+    }
+
+    #[test]
+    fn duplicate_foreign_attributes() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            r##"<svg><use xlink:href="#a" xlink:href="#b" viewbox="0 0 1 1" viewBox="x"/></svg>"##,
+            Some(Encoding::UTF8),
+        );
+
+        let document = DocumentBuilder::new_document();
+        let result =
+            Html5Parser::parse_document(&mut chars, Document::clone(&document), None).unwrap();
+
+        let binding = document.get();
+        let element = binding.get_elements_by_tag_name("use")[0];
+        let NodeData::Element(element) = &element.data else {
+            panic!()
+        };
+
+        // Duplicates are dropped before the names are adjusted, so the first one is kept
+        assert_eq!(element.attributes.len(), 2);
+        assert_eq!(element.attributes.get("xlink href").unwrap(), "#a");
+        assert_eq!(element.attributes.get("viewBox").unwrap(), "0 0 1 1");
+
+        let duplicates = result
+            .errors
+            .iter()
+            .filter(|e| e.message == "duplicate-attribute")
+            .count();
+        assert_eq!(duplicates, 2);
+    }
+
     #[test]
     fn element_with_classes_extra_whitespace() {
         let mut chars = CharIterator::new();
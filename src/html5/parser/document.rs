@@ -3,7 +3,7 @@ use crate::html5::node::arena::NodeArena;
 use crate::html5::node::data::doctype::DocTypeData;
 use crate::html5::node::data::{comment::CommentData, text::TextData};
 use crate::html5::node::HTML_NAMESPACE;
-use crate::html5::node::{Node, NodeData, NodeId};
+use crate::html5::node::{Node, NodeData, NodeId, NodeTrait, NodeType};
 use crate::html5::parser::query::SearchType;
 use crate::html5::parser::query::{Condition, Query};
 use crate::html5::parser::quirks::QuirksMode;
@@ -18,6 +18,7 @@ use std::collections::HashMap;
 use std::fmt::Display;
 use std::ops::{Deref, DerefMut};
 use std::rc::Weak;
+use std::sync::mpsc::{channel, Receiver, Sender};
 
 /// Type of the given document
 #[derive(PartialEq, Debug, Copy, Clone)]
@@ -74,6 +75,7 @@ impl DocumentFragment {
 /// These tasks are generated by a TreeBuilder which is implemented
 /// by DocumentTaskQueue which holds a handle to the actual Document
 /// to commit changes to.
+#[derive(Clone, Debug, PartialEq)]
 pub enum DocumentTask {
     CreateElement {
         name: String,
@@ -234,6 +236,86 @@ impl DocumentTaskQueue {
     }
 }
 
+/// Tree builder that does not build a DOM at all, but forwards every mutation as a
+/// DocumentTask over a channel. This allows a consumer (for instance on another thread) to
+/// process the elements as a stream while they are generated.
+///
+/// The parser streams into it with `Html5Parser::parse_document_with_sink()`.
+///
+/// Node IDs are generated the same way a fresh document would generate them, so the first
+/// created node gets ID 1 as the document root is ID 0.
+pub struct DocumentTaskSender {
+    /// Next ID to hand out for a created node
+    next_node_id: NodeId,
+    /// Channel the tasks are sent to
+    sender: Sender<DocumentTask>,
+}
+
+impl DocumentTaskSender {
+    /// Creates a new sender together with the receiving end of the task stream
+    pub fn new() -> (Self, Receiver<DocumentTask>) {
+        let (sender, receiver) = channel();
+        let task_sender = Self {
+            next_node_id: NodeId::root().next(),
+            sender,
+        };
+
+        (task_sender, receiver)
+    }
+
+    /// Sends the task and returns a newly generated ID for it. When the receiving end is gone,
+    /// the task is silently dropped as nobody is interested in it anymore.
+    fn send_node(&mut self, task: DocumentTask) -> NodeId {
+        let _ = self.sender.send(task);
+
+        let new_id = self.next_node_id;
+        self.next_node_id = self.next_node_id.next();
+        new_id
+    }
+}
+
+// See tree_builder.rs for method comments
+impl TreeBuilder for DocumentTaskSender {
+    fn create_element(
+        &mut self,
+        name: &str,
+        parent_id: NodeId,
+        position: Option<usize>,
+        namespace: &str,
+    ) -> NodeId {
+        self.send_node(DocumentTask::CreateElement {
+            name: name.to_owned(),
+            parent_id,
+            position,
+            namespace: namespace.to_owned(),
+        })
+    }
+
+    fn create_text(&mut self, content: &str, parent_id: NodeId) -> NodeId {
+        self.send_node(DocumentTask::CreateText {
+            content: content.to_owned(),
+            parent_id,
+        })
+    }
+
+    fn create_comment(&mut self, content: &str, parent_id: NodeId) -> NodeId {
+        self.send_node(DocumentTask::CreateComment {
+            content: content.to_owned(),
+            parent_id,
+        })
+    }
+
+    fn insert_attribute(&mut self, key: &str, value: &str, element_id: NodeId) -> Result<()> {
+        self.sender
+            .send(DocumentTask::InsertAttribute {
+                key: key.to_owned(),
+                value: value.to_owned(),
+                element_id,
+            })
+            .map_err(|_| Error::DocumentTask("task receiver has been dropped".to_owned()))
+    }
+}
+
 /// Defines a document
 #[derive(Debug, PartialEq)]
 pub struct Document {
@@ -303,6 +385,42 @@ impl Document {
         self.arena.get_node_mut(*node_id)
     }
 
+    /// Returns the element with the given id attribute, or None when no such element exists
+    pub fn get_element_by_id(&self, id: &str) -> Option<&Node> {
+        self.get_node_by_named_id(id)
+    }
+
+    /// Returns all elements with the given tag name in document order. The name "*" matches
+    /// all elements.
+    pub fn get_elements_by_tag_name(&self, name: &str) -> Vec<&Node> {
+        struct TagNameCollector<'a> {
+            name: &'a str,
+            found: Vec<NodeId>,
+        }
+
+        impl NodeVisitor for TagNameCollector<'_> {
+            fn enter(&mut self, node: &Node) {
+                if node.type_of() == NodeType::Element
+                    && (self.name == "*" || node.name == self.name)
+                {
+                    self.found.push(node.id);
+                }
+            }
+        }
+
+        let mut collector = TagNameCollector {
+            name,
+            found: vec![],
+        };
+        self.visit(&mut collector);
+
+        collector
+            .found
+            .iter()
+            .filter_map(|id| self.get_node_by_id(*id))
+            .collect()
+    }
+
     /// Retrieves the next sibling NodeId (to the right) of the reference_node or None.
     pub fn get_next_sibling(&self, reference_node: NodeId) -> Option<NodeId> {
         let node = self.get_node_by_id(reference_node)?;
@@ -563,6 +681,45 @@ impl Document {
     }
 }
 
+/// Hooks for walking a document with `Document::visit()`. Both hooks do nothing by default, so a
+/// visitor only implements the ones it needs.
+pub trait NodeVisitor {
+    /// Called when a node is entered, before any of its children are visited
+    fn enter(&mut self, _node: &Node) {}
+
+    /// Called when a node is left, after all of its children have been visited
+    fn leave(&mut self, _node: &Node) {}
+}
+
+impl Document {
+    /// Walks all nodes of the document in document order, calling the enter and leave hooks of
+    /// the visitor for each node
+    pub fn visit<V: NodeVisitor>(&self, visitor: &mut V) {
+        self.visit_node(NodeId::root(), visitor);
+    }
+
+    /// Walks the given node and all its descendants in document order
+    pub fn visit_node<V: NodeVisitor>(&self, node_id: NodeId, visitor: &mut V) {
+        // The stack holds the nodes to visit, and whether their children have been visited
+        let mut stack = vec![(node_id, false)];
+
+        while let Some((node_id, visited)) = stack.pop() {
+            let Some(node) = self.get_node_by_id(node_id) else {
+                continue;
+            };
+
+            if visited {
+                visitor.leave(node);
+                continue;
+            }
+
+            visitor.enter(node);
+            stack.push((node_id, true));
+            stack.extend(node.children.iter().rev().map(|child| (*child, false)));
+        }
+    }
+}
+
 impl Display for Document {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.print_tree(self.get_root(), String::new(), true, f);
@@ -934,10 +1091,15 @@ impl Iterator for TreeIterator {
 
 #[cfg(test)]
 mod tests {
+    use crate::bytes::{CharIterator, Encoding};
     use crate::html5::node::{NodeTrait, NodeType, HTML_NAMESPACE};
-    use crate::html5::parser::document::{DocumentBuilder, DocumentTaskQueue, TreeIterator};
+    use crate::html5::parser::document::{
+        Document, DocumentBuilder, DocumentTask, DocumentTaskQueue, DocumentTaskSender,
+        NodeVisitor, TreeIterator,
+    };
     use crate::html5::parser::query::Query;
     use crate::html5::parser::tree_builder::TreeBuilder;
+    use crate::html5::parser::Html5Parser;
     use crate::html5::parser::{Node, NodeData, NodeId};
     use std::collections::HashMap;
 
@@ -1164,6 +1326,134 @@ mod tests {
         assert_eq!(p_element.attributes.get("id").unwrap(), "myid");
     }
 
+    #[test]
+    fn document_task_sender() {
+        // Streams the following structure without building a document:
+        // <div id="outer">
+        //   <ul>
+        //     <li>one</li>
+        //     <li>two<!-- last --></li>
+        //   </ul>
+        // </div>
+        let (mut sender, receiver) = DocumentTaskSender::new();
+
+        let div_id = sender.create_element("div", NodeId::root(), None, HTML_NAMESPACE);
+        sender.insert_attribute("id", "outer", div_id).unwrap();
+        let ul_id = sender.create_element("ul", div_id, None, HTML_NAMESPACE);
+        let li1_id = sender.create_element("li", ul_id, None, HTML_NAMESPACE);
+        let text1_id = sender.create_text("one", li1_id);
+        let li2_id = sender.create_element("li", ul_id, None, HTML_NAMESPACE);
+        sender.create_text("two", li2_id);
+        let comment_id = sender.create_comment("last", li2_id);
+
+        assert_eq!(div_id, NodeId::from(1));
+        assert_eq!(text1_id, NodeId::from(4));
+        assert_eq!(comment_id, NodeId::from(7));
+
+        // dropping the sender closes the stream, so the receiver can be drained completely
+        drop(sender);
+        let element = |name: &str, parent_id: NodeId| DocumentTask::CreateElement {
+            name: name.to_owned(),
+            parent_id,
+            position: None,
+            namespace: HTML_NAMESPACE.to_owned(),
+        };
+        let tasks: Vec<DocumentTask> = receiver.iter().collect();
+        assert_eq!(
+            tasks,
+            vec![
+                element("div", NodeId::root()),
+                DocumentTask::InsertAttribute {
+                    key: "id".to_owned(),
+                    value: "outer".to_owned(),
+                    element_id: div_id,
+                },
+                element("ul", div_id),
+                element("li", ul_id),
+                DocumentTask::CreateText {
+                    content: "one".to_owned(),
+                    parent_id: li1_id,
+                },
+                element("li", ul_id),
+                DocumentTask::CreateText {
+                    content: "two".to_owned(),
+                    parent_id: li2_id,
+                },
+                DocumentTask::CreateComment {
+                    content: "last".to_owned(),
+                    parent_id: li2_id,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stream_parsed_document() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<!DOCTYPE html><div id=outer><ul><li>one &amp; two<li><b>three</b><!-- last --></ul></div>",
+            Some(Encoding::UTF8),
+        );
+
+        // The tasks are drained on another thread while the document is being parsed
+        let (mut sender, receiver) = DocumentTaskSender::new();
+        let consumer = std::thread::spawn(move || receiver.iter().collect::<Vec<DocumentTask>>());
+
+        let document = DocumentBuilder::new_document();
+        Html5Parser::parse_document_with_sink(
+            &mut chars,
+            Document::clone(&document),
+            &mut sender,
+            None,
+        )
+        .unwrap();
+        drop(sender);
+
+        let element = |name: &str, parent_id: usize| DocumentTask::CreateElement {
+            name: name.to_owned(),
+            parent_id: NodeId::from(parent_id),
+            position: None,
+            namespace: HTML_NAMESPACE.to_owned(),
+        };
+        let text = |content: &str, parent_id: usize| DocumentTask::CreateText {
+            content: content.to_owned(),
+            parent_id: NodeId::from(parent_id),
+        };
+        assert_eq!(
+            consumer.join().unwrap(),
+            vec![
+                element("html", 0),
+                element("head", 1),
+                element("body", 1),
+                element("div", 3),
+                DocumentTask::InsertAttribute {
+                    key: "id".to_owned(),
+                    value: "outer".to_owned(),
+                    element_id: NodeId::from(4),
+                },
+                element("ul", 4),
+                element("li", 5),
+                text("one & two", 6),
+                element("li", 5),
+                element("b", 8),
+                text("three", 9),
+                DocumentTask::CreateComment {
+                    content: " last ".to_owned(),
+                    parent_id: NodeId::from(8),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn document_task_sender_dropped_receiver() {
+        let (mut sender, receiver) = DocumentTaskSender::new();
+        drop(receiver);
+
+        let div_id = sender.create_element("div", NodeId::root(), None, HTML_NAMESPACE);
+        assert!(sender.insert_attribute("id", "x", div_id).is_err());
+    }
+
     #[test]
     fn task_queue_insert_attribute_failues() {
         let document = DocumentBuilder::new_document();
@@ -1783,4 +2073,73 @@ mod tests {
         current_node_id = tree_iterator.next();
         assert_eq!(current_node_id.unwrap(), div_id_2);
     }
+
+    #[test]
+    fn visit_elements_in_document_order() {
+        struct ElementCollector {
+            names: Vec<String>,
+            depth: usize,
+            max_depth: usize,
+        }
+
+        impl NodeVisitor for ElementCollector {
+            fn enter(&mut self, node: &Node) {
+                if node.type_of() == NodeType::Element {
+                    self.names.push(node.name.clone());
+                }
+                self.depth += 1;
+                self.max_depth = self.max_depth.max(self.depth);
+            }
+
+            fn leave(&mut self, _node: &Node) {
+                self.depth -= 1;
+            }
+        }
+
+        let mut doc = DocumentBuilder::new_document();
+        let html_id = doc.create_element("html", NodeId::root(), None, HTML_NAMESPACE);
+        let body_id = doc.create_element("body", html_id, None, HTML_NAMESPACE);
+        let div_id = doc.create_element("div", body_id, None, HTML_NAMESPACE);
+        doc.create_element("p", div_id, None, HTML_NAMESPACE);
+        doc.create_text("text", div_id);
+        doc.create_element("span", body_id, None, HTML_NAMESPACE);
+
+        let mut visitor = ElementCollector {
+            names: vec![],
+            depth: 0,
+            max_depth: 0,
+        };
+        doc.get().visit(&mut visitor);
+
+        assert_eq!(visitor.names, vec!["html", "body", "div", "p", "span"]);
+        assert_eq!(visitor.depth, 0);
+        // document > html > body > div > p
+        assert_eq!(visitor.max_depth, 5);
+    }
+
+    #[test]
+    fn get_elements_by_tag_name_and_id() {
+        let mut doc = DocumentBuilder::new_document();
+        let html_id = doc.create_element("html", NodeId::root(), None, HTML_NAMESPACE);
+        let body_id = doc.create_element("body", html_id, None, HTML_NAMESPACE);
+        let outer_id = doc.create_element("div", body_id, None, HTML_NAMESPACE);
+        let p_id = doc.create_element("p", outer_id, None, HTML_NAMESPACE);
+        let inner_id = doc.create_element("div", p_id, None, HTML_NAMESPACE);
+        doc.create_text("div", inner_id);
+        doc.insert_attribute("id", "inner", inner_id).unwrap();
+
+        let binding = doc.get();
+        let divs = binding.get_elements_by_tag_name("div");
+        assert_eq!(
+            divs.iter().map(|node| node.id).collect::<Vec<_>>(),
+            vec![outer_id, inner_id]
+        );
+        assert!(binding.get_elements_by_tag_name("span").is_empty());
+        assert_eq!(binding.get_elements_by_tag_name("*").len(), 5);
+
+        let inner = binding.get_element_by_id("inner").unwrap();
+        assert_eq!(inner.id, inner_id);
+        assert_eq!(inner.parent, Some(p_id));
+        assert!(binding.get_element_by_id("outer").is_none());
+    }
 }
@@ -3,6 +3,7 @@ use crate::html5::node::data::text::TextData;
 use crate::html5::node::{Node, NodeData, NodeId, HTML_NAMESPACE};
 use crate::html5::parser::{ActiveElement, Html5Parser, Scope};
 use crate::html5::tokenizer::token::Token;
+use alloc::rc::Rc;
 use std::collections::HashMap;
 
 use super::document::{Document, DocumentHandle};
@@ -10,6 +11,12 @@ use super::document::{Document, DocumentHandle};
 const ADOPTION_AGENCY_OUTER_LOOP_DEPTH: usize = 8;
 const ADOPTION_AGENCY_INNER_LOOP_DEPTH: usize = 3;
 
+/// Elements that are never closed to limit the depth of the tree (see `limit_tree_depth()`)
+const DEPTH_PROTECTED_ELEMENTS: [&str; 15] = [
+    "html", "head", "body", "template", "table", "caption", "colgroup", "tbody", "thead", "tfoot",
+    "tr", "td", "th", "select", "frameset",
+];
+
 #[derive(Debug)]
 pub enum InsertionPositionMode<NodeId> {
     LastChild {
@@ -82,14 +89,38 @@ impl Html5Parser<'_> {
                 let parent_node = get_node_by_id!(doc, parent);
                 let position = parent_node.children.iter().position(|&x| x == before);
                 doc.attach_node_to_parent(node, parent, position);
+                self.forward_inserted_node(&doc, node);
             }
             InsertionPositionMode::LastChild { handle, parent } => {
                 let mut doc = handle;
                 doc.attach_node_to_parent(node, parent, None);
+                self.forward_inserted_node(&doc, node);
             }
         }
     }
 
+    /// Forwards a node that has been inserted into the given document to the tree sink, if any.
+    /// Nodes inserted into the contents of a template are not forwarded.
+    fn forward_inserted_node(&mut self, handle: &DocumentHandle, node_id: NodeId) {
+        let Some(sink) = self.tree_sink.as_mut() else {
+            return;
+        };
+        if Rc::ptr_eq(&handle.0, &self.document.0) {
+            sink.node_inserted(&self.document.get(), node_id);
+        }
+    }
+
+    /// Forwards text that has been added to an existing text node of the given document to the
+    /// tree sink, if any
+    fn forward_appended_text(&mut self, handle: &DocumentHandle, node_id: NodeId, text: &str) {
+        let Some(sink) = self.tree_sink.as_mut() else {
+            return;
+        };
+        if Rc::ptr_eq(&handle.0, &self.document.0) {
+            sink.text_appended(&self.document.get(), node_id, text);
+        }
+    }
+
     pub fn insert_text_helper(&mut self, position: InsertionPositionMode<NodeId>, token: &Token) {
         match position {
             InsertionPositionMode::Sibling {
@@ -103,46 +134,58 @@ impl Html5Parser<'_> {
                 match position {
                     None | Some(0) => {
                         let node = self.create_node(token, HTML_NAMESPACE);
-                        doc.add_node(node, parent, position);
+                        let node_id = doc.add_node(node, parent, position);
+                        self.forward_inserted_node(&doc, node_id);
                     }
                     Some(index) => {
                         let last_node_id = parent_node.children[index - 1];
-                        if let NodeData::Text(TextData { ref mut value, .. }) = doc
+                        let appended = if let NodeData::Text(TextData { ref mut value, .. }) = doc
                             .get_mut()
                             .get_node_by_id_mut(last_node_id)
                             .expect("node not found")
                             .data
                         {
                             value.push_str(&token.to_string());
-                            return;
+                            true
+                        } else {
+                            false
                         };
+                        if appended {
+                            self.forward_appended_text(&doc, last_node_id, &token.to_string());
+                            return;
+                        }
 
                         let node = self.create_node(token, HTML_NAMESPACE);
-                        doc.add_node(node, parent, Some(index));
+                        let node_id = doc.add_node(node, parent, Some(index));
+                        self.forward_inserted_node(&doc, node_id);
                     }
                 }
             }
             InsertionPositionMode::LastChild { handle, parent } => {
                 let mut doc = handle;
                 let parent_node = get_node_by_id!(doc, parent);
-                if let Some(last_node_id) = parent_node.children.last() {
-                    if let NodeData::Text(TextData { ref mut value, .. }) = self
+                if let Some(&last_node_id) = parent_node.children.last() {
+                    let appended = if let NodeData::Text(TextData { ref mut value, .. }) = self
                         .document
                         .get_mut()
-                        .get_node_by_id_mut(*last_node_id)
+                        .get_node_by_id_mut(last_node_id)
                         .expect("node not found")
                         .data
                     {
                         value.push_str(&token.to_string());
-                        return;
+                        true
+                    } else {
+                        false
                     };
-                    let node = self.create_node(token, HTML_NAMESPACE);
-                    doc.add_node(node, parent, None);
-                    return;
+                    if appended {
+                        self.forward_appended_text(&doc, last_node_id, &token.to_string());
+                        return;
+                    }
                 }
 
                 let node = self.create_node(token, HTML_NAMESPACE);
-                doc.add_node(node, parent, None);
+                let node_id = doc.add_node(node, parent, None);
+                self.forward_inserted_node(&doc, node_id);
             }
         }
     }
@@ -199,8 +242,42 @@ impl Html5Parser<'_> {
         self.insert_element(new_node, override_node)
     }
 
+    /// Closes the current node when the stack of open elements has reached the maximum tree
+    /// depth, so the next element is inserted next to it instead of inside it. Only the first
+    /// time this happens is reported as an error.
+    ///
+    /// Elements the insertion modes depend on (like <body>, <template> and the table elements)
+    /// are never closed this way. When one of them is the current node, the tree grows a level
+    /// deeper instead.
+    pub fn limit_tree_depth(&mut self) {
+        if self.open_elements.len() < self.max_tree_depth || self.open_elements.len() < 2 {
+            return;
+        }
+
+        let Some(&current_id) = self.open_elements.last() else {
+            return;
+        };
+        let binding = self.document.get();
+        let Some(current) = binding.get_node_by_id(current_id) else {
+            return;
+        };
+        let protected = current.is_namespace(HTML_NAMESPACE)
+            && DEPTH_PROTECTED_ELEMENTS.contains(&current.name.as_str());
+        drop(binding);
+        if protected {
+            return;
+        }
+
+        if !self.tree_depth_exceeded {
+            self.parse_error("maximum tree depth exceeded");
+            self.tree_depth_exceeded = true;
+        }
+        self.open_elements.pop();
+    }
+
     pub fn insert_element(&mut self, node: Node, override_node: Option<NodeId>) -> NodeId {
         let node_id = self.document.get_mut().add_new_node(node);
+        self.limit_tree_depth();
         let insert_position = self.appropriate_place_insert(override_node);
         self.insert_element_helper(node_id, insert_position);
 
@@ -222,13 +299,15 @@ impl Html5Parser<'_> {
     pub fn insert_document_element(&mut self, token: &Token) {
         let node = self.create_node(token, HTML_NAMESPACE);
         let node_id = self.document.get_mut().add_node(node, NodeId::root(), None);
+        self.forward_inserted_node(&Document::clone(&self.document), node_id);
         self.open_elements.push(node_id);
     }
 
     pub fn insert_comment_element(&mut self, token: &Token, insert_position: Option<NodeId>) {
         let node = self.create_node(token, HTML_NAMESPACE);
         if let Some(position) = insert_position {
-            self.document.get_mut().add_node(node, position, None);
+            let node_id = self.document.get_mut().add_node(node, position, None);
+            self.forward_inserted_node(&Document::clone(&self.document), node_id);
         } else {
             let node_id = self.document.get_mut().add_new_node(node);
             let insert_position = self.appropriate_place_insert(None);
@@ -249,20 +328,46 @@ impl Html5Parser<'_> {
         self.insert_text_helper(insertion_position, token);
     }
 
+    /// Adds the source of the text that has just been inserted to the text node of the current
+    /// node (see `Html5ParserOptions::preserve_rcdata_source`)
+    pub fn insert_text_source(&mut self, source: &str) {
+        let current_node_id = self.open_elements.last().copied().unwrap_or_default();
+        let Some(&text_node_id) = get_node_by_id!(self.document, current_node_id)
+            .children
+            .last()
+        else {
+            return;
+        };
+
+        let mut doc = self.document.get_mut();
+        let text_node = doc
+            .get_node_by_id_mut(text_node_id)
+            .expect("node not found");
+        if let NodeData::Text(TextData {
+            source: ref mut text_source,
+            ..
+        }) = text_node.data
+        {
+            text_source.get_or_insert_with(String::new).push_str(source);
+        }
+    }
+
     // @todo: where is the fragment case handled? (substep 4: https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node)
     pub fn appropriate_place_insert(
         &self,
         override_node: Option<NodeId>,
     ) -> InsertionPositionMode<NodeId> {
-        let current_node_id = current_node!(self).id;
+        // The target is borrowed instead of cloned, as it can have a lot of children
+        let current_node_id = self.open_elements.last().copied().unwrap_or_default();
         let target_id = override_node.unwrap_or(current_node_id);
-        let target_node = get_node_by_id!(self.document, target_id);
+        let doc = self.document.get();
+        let target_node = doc.get_node_by_id(target_id).expect("Node not found");
         if !(self.foster_parenting
             && ["table", "tbody", "thead", "tfoot", "tr"].contains(&target_node.name.as_str()))
         {
             if target_node.name == "template" && target_node.is_namespace(HTML_NAMESPACE) {
-                if let NodeData::Element(element) = target_node.data {
-                    if let Some(template_contents) = element.template_contents {
+                if let NodeData::Element(element) = &target_node.data {
+                    if let Some(template_contents) = &element.template_contents {
                         return InsertionPositionMode::LastChild {
                             handle: Document::clone(&template_contents.doc),
                             parent: target_id,
@@ -496,6 +601,7 @@ impl Html5Parser<'_> {
             self.document
                 .get_mut()
                 .attach_node_to_parent(new_node_id, further_block_node_id, None);
+            self.forward_inserted_node(&Document::clone(&self.document), new_node_id);
 
             // step 4.18
             match bookmark_node_id {
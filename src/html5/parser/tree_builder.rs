@@ -1,5 +1,9 @@
+use crate::html5::node::data::{comment::CommentData, text::TextData};
+use crate::html5::node::{Node, NodeData, HTML_NAMESPACE};
+use crate::html5::parser::document::Document;
 use crate::html5::parser::NodeId;
 use crate::types::Result;
+use std::collections::HashMap;
 
 /// TreeBuilder is an interface to abstract DOM tree modifications.
 ///
@@ -25,3 +29,147 @@ pub trait TreeBuilder {
     /// Insert/update an attribute for an element node.
     fn insert_attribute(&mut self, key: &str, value: &str, element_id: NodeId) -> Result<()>;
 }
+
+/// Text node that has not been forwarded yet, as the parser can still add text to it
+struct PendingText {
+    /// ID of the text node in the parsed document
+    node_id: NodeId,
+    /// ID of the parent in the tree builder
+    parent_id: NodeId,
+    content: String,
+}
+
+/// Forwards the nodes the parser inserts into its document to a tree builder, while the document
+/// is being parsed (see `Html5Parser::parse_document_with_sink()`).
+///
+/// The tree builder only knows how to create nodes, so nodes that are moved or removed after
+/// their insertion (ie: by the adoption agency algorithm) are not updated, and text and comments
+/// are always appended to their parent. Doctypes and the contents of templates are not forwarded.
+pub(crate) struct TreeBuilderSink<'a> {
+    builder: &'a mut dyn TreeBuilder,
+    /// IDs of the nodes in the tree builder, by their ID in the parsed document
+    node_ids: HashMap<NodeId, NodeId>,
+    pending_text: Option<PendingText>,
+    /// First error returned by the tree builder
+    result: Result<()>,
+}
+
+impl<'a> TreeBuilderSink<'a> {
+    pub(crate) fn new(builder: &'a mut dyn TreeBuilder) -> Self {
+        Self {
+            builder,
+            node_ids: HashMap::from([(NodeId::root(), NodeId::root())]),
+            pending_text: None,
+            result: Ok(()),
+        }
+    }
+
+    /// Forwards a node that has just been inserted into the document. Nodes that have been
+    /// forwarded already are moved around by the parser, and are skipped.
+    pub(crate) fn node_inserted(&mut self, document: &Document, node_id: NodeId) {
+        if self.node_ids.contains_key(&node_id) {
+            return;
+        }
+        self.flush_text();
+
+        let Some(node) = document.get_node_by_id(node_id) else {
+            return;
+        };
+        let Some(&parent_id) = node.parent.and_then(|parent| self.node_ids.get(&parent)) else {
+            return;
+        };
+
+        let new_id = match &node.data {
+            NodeData::Element(element) => {
+                let namespace = node.namespace.as_deref().unwrap_or(HTML_NAMESPACE);
+                let position = self.position_in_parent(document, node);
+                let new_id =
+                    self.builder
+                        .create_element(&element.name, parent_id, position, namespace);
+
+                // Attributes are stored in a hashmap, so sort them for a deterministic order
+                let mut attributes: Vec<_> = element.attributes.iter().collect();
+                attributes.sort();
+                for (key, value) in attributes {
+                    let result = self.builder.insert_attribute(key, value, new_id);
+                    self.keep_first_error(result);
+                }
+                new_id
+            }
+            NodeData::Text(TextData { value, .. }) => {
+                self.pending_text = Some(PendingText {
+                    node_id,
+                    parent_id,
+                    content: value.clone(),
+                });
+                return;
+            }
+            NodeData::Comment(CommentData { value, .. }) => {
+                self.builder.create_comment(value, parent_id)
+            }
+            NodeData::Document(_) | NodeData::DocType(_) => return,
+        };
+        self.node_ids.insert(node_id, new_id);
+    }
+
+    /// Forwards text that has been added to an existing text node. When that text node has been
+    /// forwarded already, the text ends up in a new text node next to it.
+    pub(crate) fn text_appended(&mut self, document: &Document, node_id: NodeId, text: &str) {
+        if let Some(pending) = self.pending_text.as_mut() {
+            if pending.node_id == node_id {
+                pending.content.push_str(text);
+                return;
+            }
+        }
+        self.flush_text();
+
+        let Some(node) = document.get_node_by_id(node_id) else {
+            return;
+        };
+        if let Some(&parent_id) = node.parent.and_then(|parent| self.node_ids.get(&parent)) {
+            self.pending_text = Some(PendingText {
+                node_id,
+                parent_id,
+                content: text.to_owned(),
+            });
+        }
+    }
+
+    /// Forwards any pending text, and returns the first error of the tree builder
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.flush_text();
+        self.result
+    }
+
+    fn flush_text(&mut self) {
+        if let Some(pending) = self.pending_text.take() {
+            let new_id = self
+                .builder
+                .create_text(&pending.content, pending.parent_id);
+            self.node_ids.insert(pending.node_id, new_id);
+        }
+    }
+
+    /// Returns the position of the node between the forwarded children of its parent, or None
+    /// when it is the last child
+    fn position_in_parent(&self, document: &Document, node: &Node) -> Option<usize> {
+        let parent = document.get_node_by_id(node.parent?)?;
+        let index = parent.children.iter().position(|&id| id == node.id)?;
+        if index + 1 == parent.children.len() {
+            return None;
+        }
+
+        Some(
+            parent.children[..index]
+                .iter()
+                .filter(|id| self.node_ids.contains_key(id))
+                .count(),
+        )
+    }
+
+    fn keep_first_error(&mut self, result: Result<()>) {
+        if self.result.is_ok() {
+            self.result = result;
+        }
+    }
+}
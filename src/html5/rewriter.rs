@@ -0,0 +1,265 @@
+//! Streaming HTML rewriting
+//!
+//! Runs the tokenizer over the input and calls handlers for the start tags of the elements they
+//! are registered for. Handlers can change the attributes of these elements. Only tags that have
+//! actually been changed are serialized again; everything else is copied verbatim from the input,
+//! so the output is byte-identical apart from the rewritten tags.
+use crate::bytes::CharIterator;
+use crate::html5::error_logger::ErrorLogger;
+use crate::html5::serialize::{escape, EscapeContext};
+use crate::html5::tokenizer::token::{Attribute, SpannedToken, Token};
+use crate::html5::tokenizer::{Options, ParserData, Tokenizer};
+use crate::types::{Error, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A handler that is called for every start tag of the element it is registered for
+pub type ElementHandler<'h> = Box<dyn FnMut(&mut Element) + 'h>;
+
+/// The start tag of an element as passed to an element handler
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    name: String,
+    attributes: Vec<Attribute>,
+    is_self_closing: bool,
+    modified: bool,
+}
+
+impl Element {
+    /// Returns the (lowercased) tag name of the element
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns all attributes of the element in source order
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    /// Returns the value of the given attribute, with all character references decoded
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attr| attr.name == name)
+            .map(|attr| attr.value.as_str())
+    }
+
+    /// Returns true when the element has the given attribute
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attributes.iter().any(|attr| attr.name == name)
+    }
+
+    /// Sets the value of the given attribute, or appends the attribute when it does not exist yet
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        let attribute = Attribute {
+            name: name.to_ascii_lowercase(),
+            value: value.to_string(),
+            raw_value: escape(value, EscapeContext::Attribute),
+        };
+
+        match self
+            .attributes
+            .iter_mut()
+            .find(|attr| attr.name == attribute.name)
+        {
+            Some(attr) => *attr = attribute,
+            None => self.attributes.push(attribute),
+        }
+        self.modified = true;
+    }
+
+    /// Removes the given attribute (including any duplicates) from the element
+    pub fn remove_attribute(&mut self, name: &str) {
+        let len = self.attributes.len();
+        self.attributes.retain(|attr| attr.name != name);
+        self.modified |= self.attributes.len() != len;
+    }
+
+    /// Serializes the start tag. Attribute values are written as found in the source where
+    /// possible, so only the changed attributes differ from the input.
+    fn to_html(&self) -> String {
+        let mut html = format!("<{}", self.name);
+        for attr in &self.attributes {
+            html.push(' ');
+            html.push_str(&attr.name);
+
+            if attr.raw_value.is_empty() {
+                continue;
+            }
+            if !attr.raw_value.contains('"') {
+                html.push_str(&format!("=\"{}\"", attr.raw_value));
+            } else if !attr.raw_value.contains('\'') {
+                html.push_str(&format!("='{}'", attr.raw_value));
+            } else {
+                let value = escape(&attr.value, EscapeContext::Attribute);
+                html.push_str(&format!("=\"{value}\""));
+            }
+        }
+        if self.is_self_closing {
+            html.push('/');
+        }
+        html.push('>');
+
+        html
+    }
+}
+
+/// Rewrites HTML in a single pass over the token stream. Handlers are registered by tag name
+/// (see `on()`), and are called in order of registration for each matching start tag.
+#[derive(Default)]
+pub struct Rewriter<'h> {
+    handlers: HashMap<String, Vec<ElementHandler<'h>>>,
+}
+
+impl<'h> Rewriter<'h> {
+    /// Creates a new rewriter without any handlers
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for the start tags of the given element
+    pub fn on(&mut self, tag_name: &str, handler: impl FnMut(&mut Element) + 'h) -> &mut Self {
+        self.handlers
+            .entry(tag_name.to_ascii_lowercase())
+            .or_default()
+            .push(Box::new(handler));
+        self
+    }
+
+    /// Rewrites the given input. Tokens that are not changed by any handler are copied from the
+    /// input as-is, including their original case, quoting and character references.
+    pub fn rewrite(&mut self, input: &str) -> Result<String> {
+        let mut chars = CharIterator::new();
+        chars.set_track_byte_offsets(true);
+        chars.read_from_str(input, None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            preserve_duplicate_attributes: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger);
+
+        let mut output = String::with_capacity(input.len());
+        // Everything before this byte offset has been written to the output already
+        let mut copied = 0;
+
+        loop {
+            let SpannedToken {
+                token, byte_range, ..
+            } = tokenizer.next_spanned_token(ParserData::default())?;
+
+            let Token::StartTag {
+                name,
+                is_self_closing,
+                attributes,
+            } = token
+            else {
+                if token.is_eof() {
+                    break;
+                }
+                continue;
+            };
+
+            tokenizer.set_text_state_for(&name);
+
+            let Some(handlers) = self.handlers.get_mut(&name) else {
+                continue;
+            };
+
+            let mut element = Element {
+                name,
+                attributes,
+                is_self_closing,
+                modified: false,
+            };
+            for handler in handlers.iter_mut() {
+                handler(&mut element);
+            }
+            if !element.modified {
+                continue;
+            }
+
+            let range = byte_range
+                .ok_or_else(|| Error::Parse("no source position for start tag".into()))?;
+            output.push_str(&input[copied..range.start]);
+            output.push_str(&element.to_html());
+            copied = range.end;
+        }
+
+        output.push_str(&input[copied..]);
+
+        Ok(output)
+    }
+}
+
+/// Rewrites the given input with the given handlers, keyed by tag name. See `Rewriter`.
+pub fn rewrite(input: &str, handlers: Vec<(&str, ElementHandler<'_>)>) -> Result<String> {
+    let mut rewriter = Rewriter::new();
+    for (tag_name, handler) in handlers {
+        rewriter.on(tag_name, handler);
+    }
+
+    rewriter.rewrite(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_rel_nofollow_to_links() {
+        let input = "<!DOCTYPE html>\r\n<P CLASS=intro>Go <a href='/x?a=1&amp;b=2'>here</a> or \
+            <A HREF=\"https://example.com\" rel=\"noopener\">there</A>!</P>\n\
+            <script>document.write('<a href=y>')</script><!-- <a> --><a\thref=z />";
+
+        let result = rewrite(
+            input,
+            vec![(
+                "a",
+                Box::new(|el: &mut Element| el.set_attribute("rel", "nofollow")),
+            )],
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            "<!DOCTYPE html>\r\n<P CLASS=intro>Go <a href=\"/x?a=1&amp;b=2\" rel=\"nofollow\">here</a> or \
+            <a href=\"https://example.com\" rel=\"nofollow\">there</A>!</P>\n\
+            <script>document.write('<a href=y>')</script><!-- <a> --><a href=\"z\" rel=\"nofollow\"/>"
+        );
+    }
+
+    #[test]
+    fn unchanged_input_is_copied() {
+        let input = "\u{FEFF}<p title='a \"b\"' hidden>T&eacute;xt\r\n<img src=x ALT=y></p>";
+
+        let mut seen = vec![];
+        let result = Rewriter::new()
+            .on("img", |el| {
+                seen.push(el.get_attribute("alt").unwrap().to_string())
+            })
+            .on("P", |el| {
+                // Removing an attribute that does not exist is not a change
+                el.remove_attribute("id");
+            })
+            .rewrite(input)
+            .unwrap();
+
+        assert_eq!(result, input);
+        assert_eq!(seen, vec!["y"]);
+    }
+
+    #[test]
+    fn remove_attributes() {
+        let mut rewriter = Rewriter::new();
+        rewriter.on("img", |el| el.remove_attribute("onerror"));
+
+        let result = rewriter
+            .rewrite("<img src=a.png onerror=alert(1) onerror=x title='\"a\"'><img src=b.png>")
+            .unwrap();
+        assert_eq!(result, "<img src=\"a.png\" title='\"a\"'><img src=b.png>");
+    }
+}
@@ -0,0 +1,392 @@
+//! HTML serialization
+//!
+//! Turns a document tree back into an HTML string, roughly following the HTML fragment
+//! serialization algorithm.
+//! See: https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+use crate::html5::node::data::comment::CommentData;
+use crate::html5::node::data::doctype::DocTypeData;
+use crate::html5::node::data::text::TextData;
+use crate::html5::node::{Node, NodeData, NodeId, HTML_NAMESPACE};
+use crate::html5::parser::document::{Document, DocumentHandle, NodeVisitor};
+
+/// HTML elements that have no end tag and no contents
+pub static VOID_ELEMENTS: [&str; 18] = [
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// HTML elements whose text contents are serialized without escaping. Note that RCDATA elements
+/// (title and textarea) are not part of this list: their references are decoded while parsing,
+/// so their text must be escaped again to reproduce the source (ie: `&amp;`). When the parser
+/// has kept their source (see `Html5ParserOptions::preserve_rcdata_source`), that is written
+/// instead.
+pub static RAW_TEXT_ELEMENTS: [&str; 7] = [
+    "style",
+    "script",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "plaintext",
+];
+
+/// Options for the serializer
+#[derive(Clone, Debug, PartialEq)]
+pub struct SerializeOpts {
+    /// When enabled, the contents of <noscript> are serialized without escaping
+    pub scripting_enabled: bool,
+    /// When false, raw text elements (script, style etc.) are escaped like any other text
+    pub raw_text_elements: bool,
+    /// HTML elements that are serialized without contents and end tag. Defaults to
+    /// VOID_ELEMENTS, but can be extended for custom elements (see `add_void_element()`). Pass
+    /// these to `Html5ParserOptions::custom_void_elements` as well, so they get no children.
+    pub void_elements: Vec<String>,
+}
+
+impl Default for SerializeOpts {
+    fn default() -> Self {
+        Self {
+            scripting_enabled: true,
+            raw_text_elements: true,
+            void_elements: VOID_ELEMENTS.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+impl SerializeOpts {
+    /// Registers an additional element that must be serialized as a void element
+    pub fn add_void_element(&mut self, name: &str) {
+        if !self.is_void_element(name) {
+            self.void_elements.push(name.to_string());
+        }
+    }
+
+    /// Returns true when the given element is serialized as a void element
+    pub fn is_void_element(&self, name: &str) -> bool {
+        self.void_elements.iter().any(|void| void == name)
+    }
+}
+
+/// The context in which a string is written, which decides how it must be escaped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EscapeContext {
+    /// Regular text nodes: escapes `&`, `<`, `>` and U+00A0
+    Text,
+    /// Attribute values: escapes `&`, `"` and U+00A0
+    Attribute,
+    /// Contents of raw text elements like <script> and <style>: no escaping at all
+    RawText,
+}
+
+/// Escapes the given string for the given context
+pub fn escape(s: &str, context: EscapeContext) -> String {
+    if context == EscapeContext::RawText {
+        return s.to_string();
+    }
+
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '\u{00A0}' => result.push_str("&nbsp;"),
+            '"' if context == EscapeContext::Attribute => result.push_str("&quot;"),
+            '<' if context == EscapeContext::Text => result.push_str("&lt;"),
+            '>' if context == EscapeContext::Text => result.push_str("&gt;"),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Serializes the complete document into an HTML string
+pub fn serialize_document(document: &DocumentHandle, opts: &SerializeOpts) -> String {
+    let doc = document.get();
+    let mut serializer = Serializer::new(&doc, opts, None);
+    doc.visit(&mut serializer);
+    serializer.result
+}
+
+/// Serializes the children of the given node into an HTML string (similar to innerHTML)
+pub fn serialize_children(document: &Document, node: &Node, opts: &SerializeOpts) -> String {
+    let mut serializer = Serializer::new(document, opts, Some(node.id));
+    document.visit_node(node.id, &mut serializer);
+    serializer.result
+}
+
+/// Returns true when the text children of the given node must not be escaped
+fn is_raw_text_parent(node: &Node, opts: &SerializeOpts) -> bool {
+    if !opts.raw_text_elements {
+        return false;
+    }
+
+    let NodeData::Element(element) = &node.data else {
+        return false;
+    };
+    if node.namespace.as_deref().unwrap_or(HTML_NAMESPACE) != HTML_NAMESPACE {
+        return false;
+    }
+
+    RAW_TEXT_ELEMENTS.contains(&element.name.as_str())
+        || (opts.scripting_enabled && element.name == "noscript")
+}
+
+/// Writes the nodes while the document is walked. The walk is not recursive, so deeply nested
+/// documents cannot overflow the stack.
+struct Serializer<'a> {
+    document: &'a Document,
+    opts: &'a SerializeOpts,
+    /// Node of which only the children are serialized (see `serialize_children()`)
+    container: Option<NodeId>,
+    /// Void element whose children are skipped
+    skipping: Option<NodeId>,
+    result: String,
+}
+
+impl<'a> Serializer<'a> {
+    fn new(document: &'a Document, opts: &'a SerializeOpts, container: Option<NodeId>) -> Self {
+        Self {
+            document,
+            opts,
+            container,
+            skipping: None,
+            result: String::new(),
+        }
+    }
+
+    /// Returns true when the given node itself is not written
+    fn is_skipped(&self, node: &Node) -> bool {
+        self.skipping.is_some() || self.container == Some(node.id)
+    }
+}
+
+impl NodeVisitor for Serializer<'_> {
+    fn enter(&mut self, node: &Node) {
+        if self.is_skipped(node) {
+            return;
+        }
+
+        match &node.data {
+            NodeData::Document(_) => {}
+            NodeData::DocType(DocTypeData { name, .. }) => {
+                self.result.push_str(&format!("<!DOCTYPE {name}>"));
+            }
+            NodeData::Text(TextData {
+                source: Some(source),
+                ..
+            }) => {
+                self.result.push_str(source);
+            }
+            NodeData::Text(TextData { value, .. }) => {
+                let raw_text = node
+                    .parent
+                    .and_then(|parent_id| self.document.get_node_by_id(parent_id))
+                    .is_some_and(|parent| is_raw_text_parent(parent, self.opts));
+                let context = if raw_text {
+                    EscapeContext::RawText
+                } else {
+                    EscapeContext::Text
+                };
+                self.result.push_str(&escape(value, context));
+            }
+            NodeData::Comment(CommentData { value, .. }) => {
+                self.result.push_str(&format!("<!--{value}-->"));
+            }
+            NodeData::Element(element) => {
+                self.result.push('<');
+                self.result.push_str(&element.name);
+
+                // Attributes are stored in a hashmap, so sort them for a deterministic output
+                let mut attributes: Vec<_> = element.attributes.iter().collect();
+                attributes.sort();
+                for (key, value) in attributes {
+                    self.result.push_str(&format!(
+                        " {}=\"{}\"",
+                        key,
+                        escape(value, EscapeContext::Attribute)
+                    ));
+                }
+                self.result.push('>');
+
+                let is_html = node.namespace.as_deref().unwrap_or(HTML_NAMESPACE) == HTML_NAMESPACE;
+                if is_html && self.opts.is_void_element(&element.name) {
+                    self.skipping = Some(node.id);
+                }
+            }
+        }
+    }
+
+    fn leave(&mut self, node: &Node) {
+        if self.skipping == Some(node.id) {
+            self.skipping = None;
+            return;
+        }
+        if self.is_skipped(node) {
+            return;
+        }
+
+        if let NodeData::Element(element) = &node.data {
+            self.result.push_str(&format!("</{}>", element.name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::{CharIterator, Encoding};
+    use crate::html5::parser::document::DocumentBuilder;
+    use crate::html5::parser::tree_builder::TreeBuilder;
+    use crate::html5::parser::{Html5Parser, Html5ParserOptions};
+
+    fn parse_and_serialize(input: &str, opts: &SerializeOpts) -> String {
+        parse_and_serialize_with(input, None, opts)
+    }
+
+    fn parse_and_serialize_with(
+        input: &str,
+        parser_opts: Option<Html5ParserOptions>,
+        opts: &SerializeOpts,
+    ) -> String {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(input, Some(Encoding::UTF8));
+
+        let document = DocumentBuilder::new_document();
+        let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), parser_opts);
+
+        serialize_document(&document, opts)
+    }
+
+    #[test]
+    fn escape_contexts() {
+        let s = "a & b < c > d \"e\"\u{00A0}";
+        assert_eq!(
+            escape(s, EscapeContext::Text),
+            "a &amp; b &lt; c &gt; d \"e\"&nbsp;"
+        );
+        assert_eq!(
+            escape(s, EscapeContext::Attribute),
+            "a &amp; b < c > d &quot;e&quot;&nbsp;"
+        );
+        assert_eq!(escape(s, EscapeContext::RawText), s);
+    }
+
+    #[test]
+    fn script_body_is_not_escaped() {
+        let html = parse_and_serialize(
+            "<script>if (a < b) {}</script><p>a &lt; b</p>",
+            &SerializeOpts::default(),
+        );
+        assert_eq!(
+            html,
+            "<html><head><script>if (a < b) {}</script></head><body><p>a &lt; b</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn rcdata_round_trip() {
+        let html = parse_and_serialize(
+            "<title>a &amp; b &lt;c&gt;</title><textarea>&amp;amp;</textarea>",
+            &SerializeOpts::default(),
+        );
+        assert_eq!(
+            html,
+            "<html><head><title>a &amp; b &lt;c&gt;</title></head><body><textarea>&amp;amp;</textarea></body></html>"
+        );
+
+        // With the source kept, references are written as found instead of escaped again
+        let parser_opts = || Html5ParserOptions {
+            preserve_rcdata_source: true,
+            ..Default::default()
+        };
+        for (input, expected) in [
+            ("<title>a & b</title>", "<title>a & b</title>"),
+            ("<title>&copy;</title>", "<title>&copy;</title>"),
+            ("<title>&#38; &amp</title>", "<title>&#38; &amp</title>"),
+            (
+                "<textarea>x &lt;/textarea&gt; </b></textarea>",
+                "<textarea>x &lt;/textarea&gt; </b></textarea>",
+            ),
+        ] {
+            let html =
+                parse_and_serialize_with(input, Some(parser_opts()), &SerializeOpts::default());
+            assert!(html.contains(expected), "for {input:?}: {html}");
+        }
+
+        // Text that is not in RCDATA is escaped as usual
+        let html = parse_and_serialize_with(
+            "<p>&copy;</p>",
+            Some(parser_opts()),
+            &SerializeOpts::default(),
+        );
+        assert!(html.contains("<p>©</p>"), "{html}");
+    }
+
+    #[test]
+    fn raw_text_elements_disabled() {
+        let opts = SerializeOpts {
+            raw_text_elements: false,
+            ..Default::default()
+        };
+        let html = parse_and_serialize("<style>a > b {}</style>", &opts);
+        assert_eq!(
+            html,
+            "<html><head><style>a &gt; b {}</style></head><body></body></html>"
+        );
+    }
+
+    #[test]
+    fn custom_void_element() {
+        let input = "<my-icon name=\"x\"><p>a</p><my-icon/>b";
+        let html = parse_and_serialize(input, &SerializeOpts::default());
+        assert_eq!(
+            html,
+            "<html><head></head><body><my-icon name=\"x\"><p>a</p><my-icon>b</my-icon></my-icon></body></html>"
+        );
+
+        // Registered with both the parser and the serializer, the element never gets children,
+        // so nothing is lost
+        let parser_opts = Html5ParserOptions {
+            custom_void_elements: vec!["my-icon".into()],
+            ..Default::default()
+        };
+        let mut opts = SerializeOpts::default();
+        opts.add_void_element("my-icon");
+        let html = parse_and_serialize_with(input, Some(parser_opts), &opts);
+        assert_eq!(
+            html,
+            "<html><head></head><body><my-icon name=\"x\"><p>a</p><my-icon>b</body></html>"
+        );
+    }
+
+    #[test]
+    fn attributes_and_void_elements() {
+        let html = parse_and_serialize(
+            "<!DOCTYPE html><img src=\"a.png\" alt='say \"hi\" & <bye>'><br>",
+            &SerializeOpts::default(),
+        );
+        assert_eq!(
+            html,
+            "<!DOCTYPE html><html><head></head><body><img alt=\"say &quot;hi&quot; &amp; <bye>\" src=\"a.png\"><br></body></html>"
+        );
+    }
+
+    #[test]
+    fn deeply_nested_tree() {
+        // Built directly, as the parser limits the depth of the tree
+        let mut document = DocumentBuilder::new_document();
+        let mut parent_id = NodeId::root();
+        for _ in 0..100_000 {
+            parent_id = document.create_element("div", parent_id, None, HTML_NAMESPACE);
+        }
+        document.create_text("a<b", parent_id);
+
+        let html = serialize_document(&document, &SerializeOpts::default());
+        let expected = format!(
+            "{}a&lt;b{}",
+            "<div>".repeat(100_000),
+            "</div>".repeat(100_000)
+        );
+        assert_eq!(html, expected);
+    }
+}
@@ -4,15 +4,22 @@ pub mod token;
 mod character_reference;
 mod replacement_tables;
 
+pub use character_reference::{
+    decode_entities_in_text, decode_entity, CharacterReference, EntityKind, EntityMatch, EntityRef,
+    RefContext,
+};
+
 use crate::bytes::Bytes::{self, *};
-use crate::bytes::{CharIterator, Position};
-use crate::html5::error_logger::{ErrorLogger, ParserError};
+use crate::bytes::{CharIterator, Position, Span};
+use crate::html5::error_logger::{Diagnostic, ParseErrorSink, ParserError};
 use crate::html5::node::HTML_NAMESPACE;
 use crate::html5::tokenizer::state::State;
-use crate::html5::tokenizer::token::Token;
-use crate::types::{Error, Result};
+use crate::html5::tokenizer::token::{Attribute, CommentClosing, SpannedToken, Token};
+use crate::html5::util::ascii_case_insensitive_eq;
+use crate::types::{Error, ParseError, Result};
 use std::cell::{Ref, RefCell};
 use std::collections::HashMap;
+use std::ops::Range;
 use std::rc::Rc;
 
 /// Constants that are not directly captured as visible chars
@@ -24,7 +31,28 @@ pub const CHAR_FF: char = '\u{000C}';
 pub const CHAR_SPACE: char = '\u{0020}';
 pub const CHAR_REPLACEMENT: char = '\u{FFFD}';
 
+/// Default maximum number of nested `Tokenizer::insert_input()` calls
+pub const DEFAULT_MAX_INSERTION_DEPTH: usize = 20;
+
+/// Number of characters before the current token that are kept in the input stream when
+/// tokenizing from a reader, so the tokenizer can still look back a little
+const READER_LOOKBEHIND: usize = 64;
+
+/// Returns all named character references (like "copy;" or "amp") together with their
+/// replacement characters. Note that legacy entities are present both with and without the
+/// trailing semicolon.
+#[cfg(feature = "named_entities")]
+pub fn named_entities() -> impl Iterator<Item = (&'static str, &'static str)> {
+    replacement_tables::TOKEN_NAMED_CHARS
+        .entries()
+        .map(|(name, chars)| (*name, *chars))
+}
+
 /// The tokenizer will read the input stream and emit tokens that can be used by the parser.
+///
+/// Memory: the internal buffers (consume buffer, temporary buffer and attribute name/value) are
+/// cleared instead of reallocated, so they grow to the largest item seen and are then reused.
+/// Only the emitted tokens themselves allocate, with exactly the size they need.
 pub struct Tokenizer<'stream> {
     /// HTML character input stream
     pub chars: &'stream mut CharIterator,
@@ -36,18 +64,75 @@ pub struct Tokenizer<'stream> {
     pub current_attr_name: String,
     /// Current attribute value that we need to store temporary in case we are parsing attributes
     pub current_attr_value: String,
-    /// Current attributes
+    /// Current attributes, to find duplicates
     pub current_attrs: HashMap<String, String>,
+    /// Current attributes in source order, including duplicates (only when they are preserved)
+    pub current_attr_list: Vec<Attribute>,
+    /// Current attribute value as found in the source
+    current_attr_raw_value: String,
+    /// Offset in the stream where the current attribute value starts
+    current_attr_value_start: usize,
     /// Token that is currently in the making (if any)
     pub current_token: Option<Token>,
     /// Temporary buffer
     pub temporary_buffer: String,
     /// Queue of emitted tokens. Needed because we can generate multiple tokens during iteration
     pub token_queue: Vec<Token>,
+    /// Input spans and byte ranges of the tokens in the token queue
+    span_queue: Vec<(Span, Option<Range<usize>>)>,
     /// The last emitted start token (or empty if none)
     pub last_start_token: String,
-    /// Error logger to log errors to
-    pub error_logger: Rc<RefCell<ErrorLogger>>,
+    /// Error logger to log errors to. This can be any sink, like the default `ErrorLogger`.
+    pub error_logger: Rc<RefCell<dyn ParseErrorSink>>,
+    /// When true, CDATA sections in HTML content are emitted as text instead of a bogus comment
+    pub cdata_as_text: bool,
+    /// Parse mode of the tokenizer
+    pub parse_mode: ParseMode,
+    /// When true, duplicate attributes are stored in the start tag attributes as well
+    pub preserve_duplicate_attributes: bool,
+    /// When true, unknown named references without a semicolon raise a parse error as well
+    pub error_on_unknown_entity: bool,
+    /// When true, only named references terminated by a semicolon are resolved
+    pub require_entity_semicolon: bool,
+    /// When true, tokenization halts at the first parse error
+    pub strict: bool,
+    /// When true, a `>` in a quoted attribute value is reported as a diagnostic
+    pub warn_gt_in_attribute_value: bool,
+    /// The parse error that halted tokenization in strict mode (if any)
+    error_halt: Option<ParseError>,
+    /// Resolved character references, only collected during `collect_entities()`
+    entity_refs: Option<Vec<EntityRef>>,
+    /// When true, the input span of every emitted token is recorded (see `source_map()`)
+    pub record_source_map: bool,
+    /// Input spans of all emitted tokens, in order of emission
+    source_map: Vec<Span>,
+    /// Position directly after the last emitted token
+    last_token_end: Position,
+    /// Position of the last character read in a text state. While markup (a tag, comment etc)
+    /// is being tokenized, this is the position of its opening '<'.
+    markup_start: Position,
+    /// The last text state the tokenizer has been in, which is the state of any pending text
+    text_state: State,
+    /// When true, the source of text in RCDATA (title and textarea) is kept next to the decoded
+    /// text (see `rcdata_source()`)
+    pub record_rcdata_source: bool,
+    /// Source of the last text token emitted in RCDATA (only when `record_rcdata_source` is set)
+    rcdata_source: Option<String>,
+    /// When true, the input arrives in chunks (see `feed()`). Character references and attribute
+    /// values that are cut off at the end of the current input are not resolved until more input
+    /// has been fed, or until the end of the input is marked with `finish()`.
+    pub streaming: bool,
+    /// True when tokenizing stopped halfway a character reference at the end of the input while
+    /// streaming. The stream is rewound to the `&`, but the reference counts as read.
+    awaiting_reference: bool,
+    /// Maximum number of nested insertions through `insert_input()`
+    pub max_insertion_depth: usize,
+    /// End offsets in the stream of the inserted texts that have not been fully consumed yet,
+    /// from the outermost to the innermost insertion
+    pending_insertions: Vec<usize>,
+    /// Stream ranges of all texts inserted with `insert_input()`, in order of insertion, so
+    /// restoring a checkpoint can take out the texts inserted after it
+    insertions: Vec<Range<usize>>,
 }
 
 impl<'stream> Tokenizer<'stream> {
@@ -56,6 +141,35 @@ impl<'stream> Tokenizer<'stream> {
         new_queue.extend(self.token_queue.iter().cloned());
 
         self.token_queue = new_queue;
+
+        // Reinserted tokens are not part of the input
+        let span = Span::new(self.last_token_end, self.last_token_end);
+        self.span_queue
+            .splice(0..0, first_tokens.iter().map(|_| (span, None)));
+    }
+}
+
+/// A lending stream of tokens. Each token is only lent out until the next call to `next_token()`,
+/// so filters can inspect and modify tokens in place in a single pass without collecting them.
+///
+/// Note that the tokens themselves are still owned: their names, text and attributes are copied
+/// out of the input stream, and do not borrow from the input. Use `SpannedToken::raw_source()` to
+/// get at the part of the input a token has been tokenized from.
+pub struct LendingTokenStream<'tokenizer, 'stream> {
+    tokenizer: &'tokenizer mut Tokenizer<'stream>,
+    current: Option<SpannedToken>,
+}
+
+impl LendingTokenStream<'_, '_> {
+    /// Returns the next token, or None when the end of the stream has been reached
+    pub fn next_token(&mut self) -> Result<Option<&mut SpannedToken>> {
+        let spanned = self.tokenizer.next_spanned_token(ParserData::default())?;
+        if spanned.token.is_eof() {
+            self.current = None;
+            return Ok(None);
+        }
+
+        Ok(Some(self.current.insert(spanned)))
     }
 }
 
@@ -74,12 +188,111 @@ impl Default for ParserData {
     }
 }
 
+/// Defines how the tokenizer deals with constructs that are not part of HTML
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ParseMode {
+    /// Spec compliant HTML tokenization
+    #[default]
+    Html,
+    /// Lenient XML-ish tokenization, where `<?...?>` is emitted as a processing instruction
+    /// instead of a bogus comment
+    XmlLike,
+}
+
+/// An inline script as found by `Tokenizer::extract_scripts()`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptBlock {
+    /// Attributes of the script start tag, in source order
+    pub attributes: Vec<Attribute>,
+    /// Contents of the script element, exactly as found in the source
+    pub body: String,
+    /// Location of the body in the input stream
+    pub span: Span,
+}
+
+/// The complete state of a tokenizer and its input stream at some point, as returned by
+/// `Tokenizer::checkpoint()`
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    position: Position,
+    has_read_eof: bool,
+    state: State,
+    consumed: String,
+    current_attr_name: String,
+    current_attr_value: String,
+    current_attrs: HashMap<String, String>,
+    current_attr_list: Vec<Attribute>,
+    current_attr_raw_value: String,
+    current_attr_value_start: usize,
+    current_token: Option<Token>,
+    temporary_buffer: String,
+    token_queue: Vec<Token>,
+    span_queue: Vec<(Span, Option<Range<usize>>)>,
+    last_start_token: String,
+    error_halt: Option<ParseError>,
+    error_mark: usize,
+    source_map: usize,
+    last_token_end: Position,
+    markup_start: Position,
+    text_state: State,
+    rcdata_source: Option<String>,
+    pending_insertions: Vec<usize>,
+    insertions: usize,
+    awaiting_reference: bool,
+}
+
+/// Number of tokens per type, as counted by `Tokenizer::token_stats()`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenStats {
+    pub start_tags: usize,
+    pub end_tags: usize,
+    /// Comments, including processing instructions in the XmlLike parse mode
+    pub comments: usize,
+    /// Number of characters in all text tokens
+    pub characters: usize,
+    pub doctypes: usize,
+}
+
 /// Options that can be passed to the tokenizer. Mostly needed when dealing with tests.
 pub struct Options {
     /// Sets the initial state of the tokenizer. Normally only needed when dealing with tests
     pub initial_state: State,
     /// Sets the last starting tag in the tokenizer. Normally only needed when dealing with tests
     pub last_start_tag: String,
+    /// Treat `<![CDATA[...]]>` in HTML content as text instead of a bogus comment. This is not
+    /// spec compliant, but useful when parsing XHTML-ish content.
+    pub cdata_as_text: bool,
+    /// Sets the parse mode of the tokenizer
+    pub parse_mode: ParseMode,
+    /// Enables streaming mode, where input is fed in chunks. Call `Tokenizer::finish()` after
+    /// the last chunk.
+    pub streaming: bool,
+    /// Records the input span of every emitted token (see `Tokenizer::source_map()`)
+    pub record_source_map: bool,
+    /// Keeps duplicate attributes in the attribute list of start tags as well. They still raise a
+    /// parse error, and `Token::attr_map()` only holds the first one.
+    pub preserve_duplicate_attributes: bool,
+    /// Also raises an unknown-named-character-reference error for unknown named references that
+    /// are not terminated by a semicolon (ie: `&unknown `). The text is still passed through.
+    pub error_on_unknown_entity: bool,
+    /// Only resolves named references that are terminated by a semicolon. Legacy references
+    /// without one (ie: `&copy`) are passed through as literal text. Useful for strict validators.
+    pub require_entity_semicolon: bool,
+    /// Halts tokenization at the first parse error: `next_token()` returns the error until
+    /// `Tokenizer::recover()` is called.
+    pub strict: bool,
+    /// Reports a diagnostic when a quoted attribute value contains a `>` (ie: `title="a>b"`).
+    /// This is legal HTML and not a parse error, but often points at a missing quote. The
+    /// diagnostic is sent to the error sink apart from the parse errors (see
+    /// `ParseErrorSink::add_diagnostic()`), and never halts tokenization in strict mode.
+    pub warn_gt_in_attribute_value: bool,
+    /// Maximum number of nested `Tokenizer::insert_input()` calls, where text is inserted while
+    /// previously inserted text has not been consumed yet (ie: a script writing a script that
+    /// writes). Exceeding the limit returns an error instead of growing without bounds.
+    pub max_insertion_depth: usize,
+    /// Keeps the source of text in RCDATA (title and textarea) next to the decoded text, so
+    /// character references can be serialized as written (see `Tokenizer::rcdata_source()`)
+    pub record_rcdata_source: bool,
 }
 
 impl Default for Options {
@@ -87,6 +300,17 @@ impl Default for Options {
         Self {
             initial_state: State::Data,
             last_start_tag: String::new(),
+            cdata_as_text: false,
+            parse_mode: ParseMode::Html,
+            streaming: false,
+            record_source_map: false,
+            preserve_duplicate_attributes: false,
+            error_on_unknown_entity: false,
+            require_entity_semicolon: false,
+            strict: false,
+            warn_gt_in_attribute_value: false,
+            max_insertion_depth: DEFAULT_MAX_INSERTION_DEPTH,
+            record_rcdata_source: false,
         }
     }
 }
@@ -105,41 +329,417 @@ impl<'stream> Tokenizer<'stream> {
     pub fn new(
         chars: &'stream mut CharIterator,
         opts: Option<Options>,
-        error_logger: Rc<RefCell<ErrorLogger>>,
+        error_logger: Rc<RefCell<dyn ParseErrorSink>>,
     ) -> Self {
+        let position = chars.position;
+
         return Self {
             chars,
             state: opts.as_ref().map_or(State::Data, |o| o.initial_state),
+            cdata_as_text: opts.as_ref().is_some_and(|o| o.cdata_as_text),
+            parse_mode: opts.as_ref().map_or(ParseMode::Html, |o| o.parse_mode),
+            streaming: opts.as_ref().is_some_and(|o| o.streaming),
+            preserve_duplicate_attributes: opts
+                .as_ref()
+                .is_some_and(|o| o.preserve_duplicate_attributes),
+            error_on_unknown_entity: opts.as_ref().is_some_and(|o| o.error_on_unknown_entity),
+            require_entity_semicolon: opts.as_ref().is_some_and(|o| o.require_entity_semicolon),
+            strict: opts.as_ref().is_some_and(|o| o.strict),
+            warn_gt_in_attribute_value: opts.as_ref().is_some_and(|o| o.warn_gt_in_attribute_value),
+            max_insertion_depth: opts
+                .as_ref()
+                .map_or(DEFAULT_MAX_INSERTION_DEPTH, |o| o.max_insertion_depth),
+            awaiting_reference: false,
+            pending_insertions: vec![],
+            insertions: vec![],
+            error_halt: None,
+            record_source_map: opts.as_ref().is_some_and(|o| o.record_source_map),
+            source_map: vec![],
+            entity_refs: None,
+            last_token_end: position,
+            markup_start: position,
+            text_state: State::Data,
+            record_rcdata_source: opts.as_ref().is_some_and(|o| o.record_rcdata_source),
+            rcdata_source: None,
             last_start_token: opts.map_or(String::new(), |o| o.last_start_tag),
             consumed: String::new(),
             current_token: None,
             token_queue: vec![],
+            span_queue: vec![],
             current_attr_name: String::new(),
             current_attr_value: String::new(),
             current_attrs: HashMap::new(),
+            current_attr_list: vec![],
+            current_attr_raw_value: String::new(),
+            current_attr_value_start: 0,
             temporary_buffer: String::new(),
             error_logger,
         };
     }
 
+    /// Creates a new tokenizer like `new()`, but reserves the consume buffer for at least
+    /// `capacity` bytes up front. This avoids reallocations for large text nodes.
+    #[must_use]
+    pub fn with_capacity(
+        chars: &'stream mut CharIterator,
+        opts: Option<Options>,
+        error_logger: Rc<RefCell<dyn ParseErrorSink>>,
+        capacity: usize,
+    ) -> Self {
+        let mut tokenizer = Self::new(chars, opts, error_logger);
+        tokenizer.consumed.reserve(capacity);
+        tokenizer
+    }
+
     /// Returns the current position in the stream (with line/col number and position)
     pub(crate) fn get_position(&self) -> Position {
         self.chars.position
     }
 
-    /// Retrieves the next token from the input stream or Token::EOF when the end is reached
+    /// Retrieves the next token from the input stream or Token::EOF when the end is reached.
+    /// Once the end has been reached, every following call returns Token::EOF as well.
     pub fn next_token(&mut self, parser_data: ParserData) -> Result<Token> {
-        self.consume_stream(parser_data)?;
+        Ok(self.next_spanned_token(parser_data)?.token)
+    }
+
+    /// Retrieves the next token like `next_token()`, together with the part of the input it has
+    /// been tokenized from.
+    pub fn next_spanned_token(&mut self, parser_data: ParserData) -> Result<SpannedToken> {
+        if self.error_halt.is_none() {
+            self.awaiting_reference = false;
+            self.consume_stream(parser_data)?;
+        }
+
+        // Input that has been tokenized is not needed anymore when reading from a reader, apart
+        // from a bit of look behind. Markup that is still being tokenized must be kept.
+        let keep_from = if self.is_text_state() {
+            self.chars.position.offset
+        } else {
+            self.markup_start.offset
+        };
+        self.chars
+            .release_before(keep_from.saturating_sub(READER_LOOKBEHIND));
+
+        // In strict mode, any tokens generated are kept until the tokenizer recovers
+        if let Some(error) = &self.error_halt {
+            return Err(Error::Parse(format!(
+                "{} at {}:{}",
+                error.message, error.line, error.col
+            )));
+        }
 
         if self.token_queue.is_empty() {
-            return Ok(Token::Eof);
+            let position = self.chars.position;
+            return Ok(SpannedToken {
+                token: Token::Eof,
+                span: Span::new(position, position),
+                decoded_len: 0,
+                byte_range: self.chars.byte_range(&Span::new(position, position)),
+            });
+        }
+
+        let token = self.token_queue.remove(0);
+        let (span, byte_range) = if self.span_queue.is_empty() {
+            (Span::new(self.last_token_end, self.last_token_end), None)
+        } else {
+            self.span_queue.remove(0)
+        };
+
+        let decoded_len = match &token {
+            Token::Text(text) => text.chars().count(),
+            _ => 0,
+        };
+
+        Ok(SpannedToken {
+            token,
+            span,
+            decoded_len,
+            byte_range,
+        })
+    }
+
+    /// Continues tokenizing after a strict mode parse error halted the tokenizer. Tokenization
+    /// picks up at the current position, until the next parse error is found.
+    pub fn recover(&mut self) {
+        self.error_halt = None;
+    }
+
+    /// Returns a stream that tokenizes the input in a single pass. See `LendingTokenStream`.
+    pub fn lending_token_stream(&mut self) -> LendingTokenStream<'_, 'stream> {
+        LendingTokenStream {
+            tokenizer: self,
+            current: None,
+        }
+    }
+
+    /// Captures the current state of the tokenizer and its input stream. Tokenizing can later
+    /// continue from this point with `restore()`, ie: to re-tokenize only the tail of a document
+    /// that has been edited in an editor. Errors found so far (see `ParseErrorSink::mark()`) and
+    /// texts inserted with `insert_input()` are part of the checkpoint.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            position: self.chars.position,
+            has_read_eof: self.chars.has_read_eof,
+            state: self.state,
+            consumed: self.consumed.clone(),
+            current_attr_name: self.current_attr_name.clone(),
+            current_attr_value: self.current_attr_value.clone(),
+            current_attrs: self.current_attrs.clone(),
+            current_attr_list: self.current_attr_list.clone(),
+            current_attr_raw_value: self.current_attr_raw_value.clone(),
+            current_attr_value_start: self.current_attr_value_start,
+            current_token: self.current_token.clone(),
+            temporary_buffer: self.temporary_buffer.clone(),
+            token_queue: self.token_queue.clone(),
+            span_queue: self.span_queue.clone(),
+            last_start_token: self.last_start_token.clone(),
+            error_halt: self.error_halt.clone(),
+            error_mark: self.error_logger.borrow().mark(),
+            source_map: self.source_map.len(),
+            last_token_end: self.last_token_end,
+            markup_start: self.markup_start,
+            text_state: self.text_state,
+            rcdata_source: self.rcdata_source.clone(),
+            pending_insertions: self.pending_insertions.clone(),
+            insertions: self.insertions.len(),
+            awaiting_reference: self.awaiting_reference,
+        }
+    }
+
+    /// Restores the tokenizer and its input stream to the given checkpoint. Errors, source map
+    /// entries and texts inserted with `insert_input()` after the checkpoint are dropped. Returns
+    /// an error when the input at the checkpoint has been discarded in the meantime (ie: when
+    /// reading from a reader).
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        self.chars.rewind_to(checkpoint.position.offset)?;
+        // Later insertions are taken out first, as they may lie within earlier ones
+        for range in self.insertions.drain(checkpoint.insertions..).rev() {
+            self.chars.remove_range(range);
+        }
+        self.chars.has_read_eof = checkpoint.has_read_eof;
+
+        self.state = checkpoint.state;
+        self.consumed = checkpoint.consumed;
+        self.current_attr_name = checkpoint.current_attr_name;
+        self.current_attr_value = checkpoint.current_attr_value;
+        self.current_attrs = checkpoint.current_attrs;
+        self.current_attr_list = checkpoint.current_attr_list;
+        self.current_attr_raw_value = checkpoint.current_attr_raw_value;
+        self.current_attr_value_start = checkpoint.current_attr_value_start;
+        self.current_token = checkpoint.current_token;
+        self.temporary_buffer = checkpoint.temporary_buffer;
+        self.token_queue = checkpoint.token_queue;
+        self.span_queue = checkpoint.span_queue;
+        self.last_start_token = checkpoint.last_start_token;
+        self.error_halt = checkpoint.error_halt;
+        self.error_logger
+            .borrow_mut()
+            .rollback(checkpoint.error_mark);
+        self.source_map.truncate(checkpoint.source_map);
+        self.last_token_end = checkpoint.last_token_end;
+        self.markup_start = checkpoint.markup_start;
+        self.text_state = checkpoint.text_state;
+        self.rcdata_source = checkpoint.rcdata_source;
+        self.pending_insertions = checkpoint.pending_insertions;
+        self.awaiting_reference = checkpoint.awaiting_reference;
+
+        Ok(())
+    }
+
+    /// Inserts the given text into the input stream at the current position, so it will be
+    /// tokenized next. This is needed for document.write() style insertions. When streaming and
+    /// tokenizing stopped halfway a character reference, the text is inserted after that
+    /// reference, so it can complete it.
+    ///
+    /// Inserting while previously inserted text has not been consumed yet nests the insertion.
+    /// Returns an error when this nesting exceeds `max_insertion_depth`.
+    pub fn insert_input(&mut self, text: &str) -> Result<()> {
+        let offset = if self.awaiting_reference {
+            self.chars.length
+        } else {
+            self.chars.position.offset
+        };
+
+        // Insertions that have been consumed completely are no longer nesting
+        while self
+            .pending_insertions
+            .last()
+            .is_some_and(|end| *end <= offset)
+        {
+            self.pending_insertions.pop();
+        }
+
+        if self.pending_insertions.len() >= self.max_insertion_depth {
+            return Err(Error::Parse(format!(
+                "input insertion nested deeper than {} levels",
+                self.max_insertion_depth
+            )));
         }
 
-        Ok(self.token_queue.remove(0))
+        let length = self.chars.length;
+        self.chars.insert_str_at(offset, text);
+        let inserted = self.chars.length - length;
+
+        // The outer insertions now end further down the stream
+        for end in &mut self.pending_insertions {
+            *end += inserted;
+        }
+        self.pending_insertions.push(offset + inserted);
+        self.insertions.push(offset..offset + inserted);
+
+        Ok(())
+    }
+
+    /// Tokenizes the rest of the input and returns only the character references that were
+    /// resolved (in text as well as in attribute values). The tokens themselves are dropped.
+    pub fn collect_entities(&mut self) -> Result<Vec<EntityRef>> {
+        self.entity_refs = Some(vec![]);
+
+        loop {
+            match self.next_token(ParserData::default()) {
+                Ok(Token::Eof) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    self.entity_refs = None;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(self.entity_refs.take().unwrap_or_default())
+    }
+
+    /// Tokenizes the rest of the input and counts the tokens per type. The tokens themselves are
+    /// dropped. Useful for quick profiling of a document.
+    pub fn token_stats(&mut self) -> Result<TokenStats> {
+        let mut stats = TokenStats::default();
+
+        loop {
+            match self.next_token(ParserData::default())? {
+                Token::StartTag { .. } => stats.start_tags += 1,
+                Token::EndTag { .. } => stats.end_tags += 1,
+                Token::Comment { .. } | Token::ProcessingInstruction { .. } => stats.comments += 1,
+                Token::Text(text) => stats.characters += text.chars().count(),
+                Token::DocType { .. } => stats.doctypes += 1,
+                Token::Eof => break,
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Tokenizes the rest of the input and returns all script elements found. Since there is no
+    /// tree builder, the tokenizer switches to the script data state (and the RCDATA/RAWTEXT
+    /// states for elements like <title> and <style>) on the matching start tags by itself.
+    pub fn extract_scripts(&mut self) -> Result<Vec<ScriptBlock>> {
+        let mut scripts = vec![];
+        let mut current: Option<ScriptBlock> = None;
+
+        loop {
+            // The spans of the start and end tags tell where the script bodies are
+            let SpannedToken { token, span, .. } = self.next_spanned_token(ParserData::default())?;
+
+            match token {
+                Token::StartTag {
+                    name, attributes, ..
+                } if current.is_none() => match name.as_str() {
+                    "script" => {
+                        self.set_state(State::ScriptData);
+                        current = Some(ScriptBlock {
+                            attributes,
+                            body: String::new(),
+                            span: Span::new(span.end, span.end),
+                        });
+                    }
+                    name => self.set_text_state_for(name),
+                },
+                Token::Text(text) => {
+                    if let Some(block) = current.as_mut() {
+                        block.body.push_str(&text);
+                    }
+                }
+                Token::EndTag { name, .. } if name == "script" => {
+                    if let Some(mut block) = current.take() {
+                        block.span.end = span.start;
+                        scripts.push(block);
+                    }
+                }
+                Token::Eof => {
+                    // An unclosed script runs until the end of the input
+                    if let Some(mut block) = current.take() {
+                        block.span.end = span.start;
+                        scripts.push(block);
+                    }
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(scripts)
+    }
+
+    /// Switches to the state the tree builder would select after the start tag of the given
+    /// element, so the contents of elements like <title> and <style> are tokenized as text
+    /// without a tree builder. Other elements leave the state untouched.
+    pub(crate) fn set_text_state_for(&mut self, element_name: &str) {
+        match element_name {
+            "script" => self.set_state(State::ScriptData),
+            "title" | "textarea" => self.set_state(State::RCDATA),
+            "style" | "xmp" | "iframe" | "noembed" | "noframes" => self.set_state(State::RAWTEXT),
+            "plaintext" => self.set_state(State::PLAINTEXT),
+            _ => {}
+        }
+    }
+
+    /// Reads the body of an RCDATA element (like <title> or <textarea>) up to its end tag, with
+    /// all character references resolved. The start tag must have been tokenized already, and the
+    /// end tag is consumed as well.
+    pub fn read_rcdata_text(&mut self, element_name: &str) -> Result<String> {
+        self.last_start_token = element_name.to_string();
+        self.set_state(State::RCDATA);
+
+        let mut text = String::new();
+        loop {
+            match self.next_token(ParserData::default())? {
+                Token::Text(value) => text.push_str(&value),
+                // Only the matching end tag leaves the RCDATA state
+                Token::EndTag { .. } | Token::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(text)
+    }
+
+    /// Returns the input span of every token emitted so far, in order of emission. Only filled
+    /// when the tokenizer runs with the `record_source_map` option.
+    pub fn source_map(&self) -> &[Span] {
+        &self.source_map
+    }
+
+    /// Takes the source of the last text token emitted in RCDATA (title and textarea), with
+    /// character references as written. The text token itself holds the decoded text. Only
+    /// recorded when the tokenizer runs with the `record_rcdata_source` option.
+    pub fn take_rcdata_source(&mut self) -> Option<String> {
+        self.rcdata_source.take()
+    }
+
+    /// Appends the given chunk of text to the end of the input stream. Used in streaming mode,
+    /// where an Eof token only means that all input fed so far has been tokenized.
+    pub fn feed(&mut self, text: &str) {
+        self.chars.append_str(text);
+    }
+
+    /// Marks the end of the input in streaming mode. Character references and attribute values
+    /// that are still waiting for more input are resolved as at the end of a regular stream (ie:
+    /// a trailing `&cop` becomes text), and the following Eof token is the real end of the input.
+    pub fn finish(&mut self) {
+        self.streaming = false;
     }
 
     /// Returns the error logger
-    pub fn get_error_logger(&self) -> Ref<ErrorLogger> {
+    pub fn get_error_logger(&self) -> Ref<'_, dyn ParseErrorSink> {
         self.error_logger.borrow()
     }
 
@@ -156,6 +756,11 @@ impl<'stream> Tokenizer<'stream> {
                 return Ok(());
             }
 
+            if self.is_text_state() {
+                self.markup_start = self.chars.position;
+                self.text_state = self.state;
+            }
+
             match self.state {
                 State::Data => {
                     let c = self.read_char();
@@ -177,8 +782,11 @@ impl<'stream> Tokenizer<'stream> {
                     }
                 }
                 State::CharacterReferenceInData => {
-                    self.consume_character_reference(None, false);
+                    let result = self.consume_character_reference_in(RefContext::Data);
                     self.state = State::Data;
+                    if result.need_more_input {
+                        self.emit_token(Token::Eof);
+                    }
                 }
                 State::RCDATA => {
                     let c = self.read_char();
@@ -201,8 +809,11 @@ impl<'stream> Tokenizer<'stream> {
                 }
                 State::CharacterReferenceInRcData => {
                     // consume character reference
-                    self.consume_character_reference(None, false);
+                    let result = self.consume_character_reference_in(RefContext::Data);
                     self.state = State::RCDATA;
+                    if result.need_more_input {
+                        self.emit_token(Token::Eof);
+                    }
                 }
                 State::RAWTEXT => {
                     let c = self.read_char();
@@ -267,13 +878,20 @@ impl<'stream> Tokenizer<'stream> {
                             self.current_token = Some(Token::StartTag {
                                 name: String::new(),
                                 is_self_closing: false,
-                                attributes: HashMap::new(),
+                                attributes: vec![],
                             });
                             self.chars.unread();
                             self.state = State::TagName;
                         }
+                        Ch('?') if self.parse_mode == ParseMode::XmlLike => {
+                            self.temporary_buffer.clear();
+                            self.state = State::ProcessingInstruction;
+                        }
                         Ch('?') => {
-                            self.current_token = Some(Token::Comment(String::new()));
+                            self.current_token = Some(Token::Comment {
+                                value: String::new(),
+                                closing: CommentClosing::Normal,
+                            });
                             self.parse_error(ParserError::UnexpectedQuestionMarkInsteadOfTagName);
                             self.chars.unread();
                             self.state = State::BogusComment;
@@ -314,7 +932,10 @@ impl<'stream> Tokenizer<'stream> {
                         }
                         _ => {
                             self.parse_error(ParserError::InvalidFirstCharacterOfTagName);
-                            self.current_token = Some(Token::Comment(String::new()));
+                            self.current_token = Some(Token::Comment {
+                                value: String::new(),
+                                closing: CommentClosing::Normal,
+                            });
                             self.chars.unread();
                             self.state = State::BogusComment;
                         }
@@ -1040,8 +1661,12 @@ impl<'stream> Tokenizer<'stream> {
                         Ch(CHAR_TAB | CHAR_LF | CHAR_FF | CHAR_SPACE) => {
                             // Ignore
                         }
-                        Ch('"') => self.state = State::AttributeValueDoubleQuoted,
+                        Ch('"') => {
+                            self.current_attr_value_start = self.chars.tell();
+                            self.state = State::AttributeValueDoubleQuoted;
+                        }
                         Ch('\'') => {
+                            self.current_attr_value_start = self.chars.tell();
                             self.state = State::AttributeValueSingleQuoted;
                         }
                         Ch('>') => {
@@ -1054,6 +1679,7 @@ impl<'stream> Tokenizer<'stream> {
                         }
                         _ => {
                             self.chars.unread();
+                            self.current_attr_value_start = self.chars.tell();
                             self.state = State::AttributeValueUnquoted;
                         }
                     }
@@ -1061,14 +1687,31 @@ impl<'stream> Tokenizer<'stream> {
                 State::AttributeValueDoubleQuoted => {
                     let c = self.read_char();
                     match c {
-                        Ch('"') => self.state = State::AfterAttributeValueQuoted,
+                        Ch('"') => {
+                            self.store_raw_attribute_value();
+                            self.state = State::AfterAttributeValueQuoted;
+                        }
                         Ch('&') => {
-                            self.consume_character_reference(Some(Ch('"')), true);
+                            let result =
+                                self.consume_character_reference_in(RefContext::AttributeDouble);
+                            if result.need_more_input {
+                                self.emit_token(Token::Eof);
+                            }
+                        }
+                        Ch('>') if self.warn_gt_in_attribute_value => {
+                            self.diagnostic(Diagnostic::GreaterThanSignInAttributeValue);
+                            self.current_attr_value.push('>');
                         }
                         Ch(CHAR_NUL) => {
                             self.parse_error(ParserError::UnexpectedNullCharacter);
                             self.current_attr_value.push(CHAR_REPLACEMENT);
                         }
+                        Eof if self.streaming => {
+                            // Wait in this state for the rest of the value to be fed, or for
+                            // finish() to mark the end of the input
+                            self.chars.unread();
+                            self.emit_token(Token::Eof);
+                        }
                         Eof => {
                             self.parse_error(ParserError::EofInTag);
                             self.state = State::Data;
@@ -1081,14 +1724,31 @@ impl<'stream> Tokenizer<'stream> {
                 State::AttributeValueSingleQuoted => {
                     let c = self.read_char();
                     match c {
-                        Ch('\'') => self.state = State::AfterAttributeValueQuoted,
+                        Ch('\'') => {
+                            self.store_raw_attribute_value();
+                            self.state = State::AfterAttributeValueQuoted;
+                        }
                         Ch('&') => {
-                            self.consume_character_reference(Some(Ch('\'')), true);
+                            let result =
+                                self.consume_character_reference_in(RefContext::AttributeSingle);
+                            if result.need_more_input {
+                                self.emit_token(Token::Eof);
+                            }
+                        }
+                        Ch('>') if self.warn_gt_in_attribute_value => {
+                            self.diagnostic(Diagnostic::GreaterThanSignInAttributeValue);
+                            self.current_attr_value.push('>');
                         }
                         Ch(CHAR_NUL) => {
                             self.parse_error(ParserError::UnexpectedNullCharacter);
                             self.current_attr_value.push(CHAR_REPLACEMENT);
                         }
+                        Eof if self.streaming => {
+                            // Wait in this state for the rest of the value to be fed, or for
+                            // finish() to mark the end of the input
+                            self.chars.unread();
+                            self.emit_token(Token::Eof);
+                        }
                         Eof => {
                             self.parse_error(ParserError::EofInTag);
                             self.state = State::Data;
@@ -1102,12 +1762,18 @@ impl<'stream> Tokenizer<'stream> {
                     let c = self.read_char();
                     match c {
                         Ch(CHAR_TAB | CHAR_LF | CHAR_FF | CHAR_SPACE) => {
+                            self.store_raw_attribute_value();
                             self.state = State::BeforeAttributeName;
                         }
                         Ch('&') => {
-                            self.consume_character_reference(Some(Ch('>')), true);
+                            let result =
+                                self.consume_character_reference_in(RefContext::AttributeUnquoted);
+                            if result.need_more_input {
+                                self.emit_token(Token::Eof);
+                            }
                         }
                         Ch('>') => {
+                            self.store_raw_attribute_value();
                             self.store_and_clear_current_attribute();
                             self.add_stored_attributes_to_current_token();
                             self.emit_current_token();
@@ -1123,6 +1789,12 @@ impl<'stream> Tokenizer<'stream> {
                             );
                             self.current_attr_value.push(c.into());
                         }
+                        Eof if self.streaming => {
+                            // Wait in this state for the rest of the value to be fed, or for
+                            // finish() to mark the end of the input
+                            self.chars.unread();
+                            self.emit_token(Token::Eof);
+                        }
                         Eof => {
                             self.parse_error(ParserError::EofInTag);
                             self.state = State::Data;
@@ -1179,24 +1851,54 @@ impl<'stream> Tokenizer<'stream> {
                     }
                 }
                 State::BogusComment => {
+                    // Take everything up to the closing '>' in a single pass
+                    let start = self.chars.position;
+                    let text = self.chars.read_until('>');
+                    self.add_read_text_to_token_value(start, &text);
+
                     let c = self.read_char();
                     match c {
-                        Ch('>') | Eof => {
+                        Ch('>') => {
+                            self.set_comment_closing(CommentClosing::Abrupt);
                             self.emit_current_token();
                             self.state = State::Data;
                         }
-                        Ch(CHAR_NUL) => {
-                            self.parse_error(ParserError::UnexpectedNullCharacter);
-                            self.add_to_token_value(CHAR_REPLACEMENT);
+                        Eof => {
+                            self.emit_current_token();
+                            self.state = State::Data;
                         }
                         _ => {
+                            // A surrogate, which has been replaced by read_char()
                             self.add_to_token_value(c.into());
                         }
                     }
                 }
+                State::ProcessingInstruction => {
+                    let c = self.read_char();
+                    match c {
+                        Ch('?') if self.chars.look_ahead(0) == Ch('>') => {
+                            self.chars.skip(1);
+                            self.emit_processing_instruction();
+                            self.state = State::Data;
+                        }
+                        Eof => {
+                            self.parse_error(ParserError::EofInTag);
+                            self.emit_processing_instruction();
+                            self.state = State::Data;
+                        }
+                        Ch(CHAR_NUL) => {
+                            self.parse_error(ParserError::UnexpectedNullCharacter);
+                            self.temporary_buffer.push(CHAR_REPLACEMENT);
+                        }
+                        _ => self.temporary_buffer.push(c.into()),
+                    }
+                }
                 State::MarkupDeclarationOpen => {
                     if self.chars.look_ahead_slice(2) == "--" {
-                        self.current_token = Some(Token::Comment(String::new()));
+                        self.current_token = Some(Token::Comment {
+                            value: String::new(),
+                            closing: CommentClosing::Normal,
+                        });
 
                         // Skip the two -- signs
                         self.chars.skip(2);
@@ -1214,13 +1916,18 @@ impl<'stream> Tokenizer<'stream> {
                     if self.chars.look_ahead_slice(7) == "[CDATA[" {
                         self.chars.skip(7);
 
-                        if parser_data.adjusted_node_namespace != HTML_NAMESPACE {
+                        if parser_data.adjusted_node_namespace != HTML_NAMESPACE
+                            || self.cdata_as_text
+                        {
                             self.state = State::CDATASection;
                             continue;
                         }
 
                         self.parse_error(ParserError::CdataInHtmlContent);
-                        self.current_token = Some(Token::Comment("[CDATA[".into()));
+                        self.current_token = Some(Token::Comment {
+                            value: "[CDATA[".into(),
+                            closing: CommentClosing::Normal,
+                        });
 
                         self.state = State::BogusComment;
                         continue;
@@ -1229,7 +1936,10 @@ impl<'stream> Tokenizer<'stream> {
                     self.chars.read_char();
                     self.parse_error(ParserError::IncorrectlyOpenedComment);
                     self.chars.unread();
-                    self.current_token = Some(Token::Comment(String::new()));
+                    self.current_token = Some(Token::Comment {
+                        value: String::new(),
+                        closing: CommentClosing::Normal,
+                    });
 
                     self.state = State::BogusComment;
                 }
@@ -1241,6 +1951,7 @@ impl<'stream> Tokenizer<'stream> {
                         }
                         Ch('>') => {
                             self.parse_error(ParserError::AbruptClosingOfEmptyComment);
+                            self.set_comment_closing(CommentClosing::Abrupt);
                             self.emit_current_token();
                             self.state = State::Data;
                         }
@@ -1258,6 +1969,7 @@ impl<'stream> Tokenizer<'stream> {
                         }
                         Ch('>') => {
                             self.parse_error(ParserError::AbruptClosingOfEmptyComment);
+                            self.set_comment_closing(CommentClosing::Abrupt);
                             self.emit_current_token();
                             self.state = State::Data;
                         }
@@ -1392,6 +2104,7 @@ impl<'stream> Tokenizer<'stream> {
                         }
                         Ch('>') => {
                             self.parse_error(ParserError::IncorrectlyClosedComment);
+                            self.set_comment_closing(CommentClosing::Bang);
                             self.emit_current_token();
                             self.state = State::Data;
                         }
@@ -1989,11 +2702,55 @@ impl<'stream> Tokenizer<'stream> {
 
     /// Adds the given character to the current token's value (if applicable)
     fn add_to_token_value(&mut self, c: char) {
-        if let Some(Token::Comment(value)) = &mut self.current_token {
+        if let Some(Token::Comment { value, .. }) = &mut self.current_token {
             value.push(c);
         }
     }
 
+    /// Adds the given string to the current token's value (if applicable)
+    fn add_str_to_token_value(&mut self, s: &str) {
+        if let Some(Token::Comment { value, .. }) = &mut self.current_token {
+            value.push_str(s);
+        }
+    }
+
+    /// Adds text that has been read from the given position at once (see
+    /// `CharIterator::read_until()`) to the current token's value. The parse errors that
+    /// `read_char()` would have raised are reported afterwards, on the position of each character,
+    /// and null characters are replaced.
+    fn add_read_text_to_token_value(&mut self, start: Position, text: &str) {
+        let needs_error = |c: char| {
+            c == CHAR_NUL || self.is_control_char(c as u32) || self.is_noncharacter(c as u32)
+        };
+        if !text.contains(needs_error) {
+            self.add_str_to_token_value(text);
+            return;
+        }
+
+        let mut pos = start;
+        for c in text.chars() {
+            if c == CHAR_NUL {
+                self.parse_error_at(pos, ParserError::UnexpectedNullCharacter);
+                self.add_to_token_value(CHAR_REPLACEMENT);
+            } else {
+                if self.is_control_char(c as u32) {
+                    self.parse_error_at(pos, ParserError::ControlCharacterInInputStream);
+                } else if self.is_noncharacter(c as u32) {
+                    self.parse_error_at(pos, ParserError::NoncharacterInInputStream);
+                }
+                self.add_to_token_value(c);
+            }
+            pos = self.chars.position_after(pos, Ch(c));
+        }
+    }
+
+    /// Sets how the current comment token has been closed (if applicable)
+    fn set_comment_closing(&mut self, new_closing: CommentClosing) {
+        if let Some(Token::Comment { closing, .. }) = &mut self.current_token {
+            *closing = new_closing;
+        }
+    }
+
     /// Sets the public identifier of the current token (if applicable)
     fn set_public_identifier(&mut self, s: String) {
         if let Some(Token::DocType { pub_identifier, .. }) = &mut self.current_token {
@@ -2047,6 +2804,21 @@ impl<'stream> Tokenizer<'stream> {
         }
     }
 
+    /// Emits the processing instruction found in the temporary buffer. The target is everything
+    /// up to the first whitespace, the data is the rest without leading whitespace.
+    fn emit_processing_instruction(&mut self) {
+        let content = std::mem::take(&mut self.temporary_buffer);
+        let (target, data) = match content.find(|c: char| c.is_ascii_whitespace()) {
+            Some(idx) => (&content[..idx], content[idx..].trim_start()),
+            None => (content.as_str(), ""),
+        };
+
+        self.emit_token(Token::ProcessingInstruction {
+            target: target.to_string(),
+            data: data.to_string(),
+        });
+    }
+
     /// Emits the current stored token
     fn emit_current_token(&mut self) {
         if let Some(t) = self.current_token.take() {
@@ -2054,23 +2826,85 @@ impl<'stream> Tokenizer<'stream> {
         }
     }
 
+    /// Returns true when the tokenizer is currently inside an attribute value. Character
+    /// references found in this state are flushed into the attribute value instead of the
+    /// consume buffer.
+    pub(crate) fn in_attribute(&self) -> bool {
+        matches!(
+            self.state,
+            State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted
+        )
+    }
+
+    /// Returns true when the tokenizer is in a state where characters are emitted as text
+    fn is_text_state(&self) -> bool {
+        matches!(
+            self.state,
+            State::Data
+                | State::RCDATA
+                | State::RAWTEXT
+                | State::PLAINTEXT
+                | State::ScriptData
+                | State::ScriptDataEscaped
+                | State::ScriptDataEscapedDash
+                | State::ScriptDataEscapedDashDash
+        )
+    }
+
+    /// Queues the input span of a token that is about to be emitted, and records it in the source
+    /// map when asked. Any pending text ends where the markup of the token starts.
+    fn record_span(&mut self, token: &Token, flushes_text: bool) {
+        let end = self.chars.position;
+        let start = match token {
+            Token::Text(..) | Token::Eof => end,
+            _ => self.markup_start,
+        };
+
+        if flushes_text {
+            self.queue_span(Span::new(self.last_token_end, start));
+        }
+        match token {
+            Token::Text(..) => self.queue_span(Span::new(self.last_token_end, end)),
+            _ => self.queue_span(Span::new(start, end)),
+        }
+    }
+
+    fn queue_span(&mut self, span: Span) {
+        self.span_queue.push((span, self.chars.byte_range(&span)));
+        if self.record_source_map {
+            self.source_map.push(span);
+        }
+    }
+
     /// Emits the given stored token. It does not have to be stored first.
     fn emit_token(&mut self, token: Token) {
+        self.record_span(&token, self.has_consumed_data());
+
         // Save the start token name if we are pushing it. This helps us in detecting matching tags.
         if let Token::StartTag { name, .. } = &token {
-            self.last_start_token = String::from(name);
+            self.last_start_token.clear();
+            self.last_start_token.push_str(name);
         }
 
-        // If there is any consumed data, emit this first as a text token
+        // If there is any consumed data, emit this first as a text token. The token gets a copy
+        // of the data, so the consume buffer keeps its capacity and is reused for the next token.
         if self.has_consumed_data() {
-            let value = self.get_consumed_str().to_string();
-
-            self.token_queue.push(Token::Text(value.to_string()));
+            if self.record_rcdata_source && self.text_state == State::RCDATA {
+                let end = match token {
+                    Token::Eof => self.chars.position.offset,
+                    _ => self.markup_start.offset,
+                };
+                self.rcdata_source = Some(self.chars.slice(self.last_token_end.offset, end));
+            }
+            self.token_queue.push(Token::Text(self.consumed.clone()));
 
             self.clear_consume_buffer();
         }
 
         self.token_queue.push(token);
+        self.last_token_end = self.chars.position;
     }
 
     // Consumes the given character
@@ -2097,10 +2931,11 @@ impl<'stream> Tokenizer<'stream> {
     /// Return true when the given end_token matches the stored start token (ie: 'table' matches when
     /// last_start_token = 'table')
     fn is_appropriate_end_token(&self, end_token: &str) -> bool {
-        self.last_start_token == end_token
+        ascii_case_insensitive_eq(&self.last_start_token, end_token)
     }
 
-    /// Return the consumed string as a String
+    /// Returns the consume buffer (the text consumed so far for the current text token) without
+    /// clearing it. Useful to inspect in-progress consumption when debugging.
     pub fn get_consumed_str(&self) -> &str {
         &self.consumed
     }
@@ -2115,10 +2950,41 @@ impl<'stream> Tokenizer<'stream> {
         self.consumed.clear();
     }
 
+    /// Reports a diagnostic, which is not a parse error according to the spec. Diagnostics are
+    /// passed to the sink separately from the parse errors, and never halt tokenization in strict
+    /// mode.
+    pub(crate) fn diagnostic(&mut self, message: Diagnostic) {
+        let pos = self.chars.get_previous_position();
+        self.error_logger
+            .borrow_mut()
+            .add_diagnostic(pos, message.as_str());
+    }
+
     /// Creates a parser log error message
     pub(crate) fn parse_error(&mut self, message: ParserError) {
         // The previous position is where the error occurred
         let pos = self.chars.get_previous_position();
+        self.parse_error_at(pos, message);
+    }
+
+    /// Creates a parser log error message for an error at the given position
+    fn parse_error_at(&mut self, pos: Position, message: ParserError) {
+        #[cfg(feature = "log_parse_errors")]
+        log::warn!(
+            "parse error at {}:{}: {}",
+            pos.line,
+            pos.col,
+            message.as_str()
+        );
+
+        if self.strict && self.error_halt.is_none() {
+            self.error_halt = Some(ParseError {
+                message: message.as_str().to_string(),
+                line: pos.line,
+                col: pos.col,
+                offset: pos.offset,
+            });
+        }
 
         self.error_logger
             .borrow_mut()
@@ -2150,7 +3016,7 @@ impl<'stream> Tokenizer<'stream> {
     /// Adds a new attribute to the current token
     fn set_add_attribute_to_current_token(&mut self, name: &str, value: &str) {
         if let Token::StartTag { attributes, .. } = &mut self.current_token.as_mut().unwrap() {
-            attributes.insert(name.into(), value.into());
+            attributes.push(Attribute::new(name, value));
         }
 
         self.current_attr_name.clear();
@@ -2179,17 +3045,37 @@ impl<'stream> Tokenizer<'stream> {
 
     /// Saves the current attribute name and value onto the current_attrs stack, if there is anything to store
     fn store_and_clear_current_attribute(&mut self) {
-        if !self.current_attr_name.is_empty()
-            && !self.current_attrs.contains_key(&self.current_attr_name)
-        {
-            self.current_attrs.insert(
-                self.current_attr_name.clone(),
-                self.current_attr_value.clone(),
-            );
+        if !self.current_attr_name.is_empty() {
+            let is_duplicate = self.current_attrs.contains_key(&self.current_attr_name);
+            if !is_duplicate || self.preserve_duplicate_attributes {
+                self.current_attr_list.push(Attribute {
+                    name: self.current_attr_name.clone(),
+                    value: self.current_attr_value.clone(),
+                    raw_value: self.current_attr_raw_value.clone(),
+                });
+            }
+
+            if !is_duplicate {
+                self.current_attrs.insert(
+                    self.current_attr_name.clone(),
+                    self.current_attr_value.clone(),
+                );
+            }
         }
 
-        self.current_attr_name = String::new();
-        self.current_attr_value = String::new();
+        // Clearing (instead of replacing) keeps the allocated buffers for the next attribute
+        self.current_attr_name.clear();
+        self.current_attr_value.clear();
+        self.current_attr_raw_value.clear();
+    }
+
+    /// Stores the source of the attribute value that has just been completed. The character that
+    /// terminated the value (a quote, whitespace or '>') has already been read.
+    fn store_raw_attribute_value(&mut self) {
+        let end = self.chars.tell() - 1;
+        self.current_attr_raw_value.clear();
+        self.current_attr_raw_value
+            .push_str(&self.chars.slice(self.current_attr_value_start, end));
     }
 
     /// This method will add current generated attributes to the current (start) token if needed.
@@ -2204,14 +3090,1704 @@ impl<'stream> Tokenizer<'stream> {
         match self.current_token.as_mut().expect("current token") {
             Token::EndTag { .. } => {
                 self.parse_error(ParserError::EndTagWithAttributes);
+                self.current_attr_list.clear();
             }
             Token::StartTag { attributes, .. } => {
-                for (key, value) in &self.current_attrs {
-                    attributes.insert(key.clone(), value.clone());
-                }
                 self.current_attrs = HashMap::new();
+                *attributes = std::mem::take(&mut self.current_attr_list);
             }
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::CharIterator;
+    use crate::html5::error_logger::ErrorLogger;
+    use std::io::Read;
+
+    /// Runs the given function on a tokenizer over the given input, and returns its result
+    pub(super) fn with_tokenizer<R>(
+        input: &str,
+        opts: Option<Options>,
+        f: impl FnOnce(&mut Tokenizer) -> R,
+    ) -> R {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(input, None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, opts, error_logger);
+
+        f(&mut tokenizer)
+    }
+
+    /// Tokenizes the given input up to the end, and returns the tokens (without the final Eof)
+    /// together with the errors found
+    pub(super) fn tokenize(input: &str, opts: Option<Options>) -> (Vec<Token>, Vec<ParseError>) {
+        with_tokenizer(input, opts, |tokenizer| {
+            let mut tokens = vec![];
+            loop {
+                let token = tokenizer.next_token(ParserData::default()).unwrap();
+                if token.is_eof() {
+                    break;
+                }
+                tokens.push(token);
+            }
+            let errors = tokenizer.get_error_logger().get_errors();
+
+            (tokens, errors)
+        })
+    }
+
+    macro_rules! comment_tests {
+        ($($name:ident : $value:expr)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (input, expected, errors): (&str, &str, &[&str]) = $value;
+
+                    let (tokens, found) = tokenize(input, None);
+                    let Some(Token::Comment { value, .. }) = tokens.first() else {
+                        panic!("expected a comment, got {tokens:?}");
+                    };
+                    assert_eq!(expected, value);
+
+                    let found = found.into_iter().map(|e| e.message).collect::<Vec<_>>();
+                    assert_eq!(errors, found.as_slice());
+                }
+            )*
+        }
+    }
+
+    comment_tests! {
+        // Comment less-than sign and its bang/dash descendants
+        comment_0: ("<!--<!-->", "<!", &[])
+        comment_1: ("<!--<!--->", "<!-", &["nested-comment"])
+        comment_2: ("<!--<!-- x -->", "<!-- x ", &["nested-comment"])
+        comment_3: ("<!--<!-x-->", "<!-x", &[])
+        comment_4: ("<!--<<!-->", "<<!", &[])
+        comment_5: ("<!--<!x-->", "<!x", &[])
+        comment_6: ("<!--a<!--b-->", "a<!--b", &["nested-comment"])
+
+        // Comment end bang and abrupt endings
+        comment_10: ("<!--a--!>", "a", &["incorrectly-closed-comment"])
+        comment_11: ("<!--a--!-->", "a--!", &[])
+        comment_12: ("<!--a--!b-->", "a--!b", &[])
+        comment_13: ("<!-->", "", &["abrupt-closing-of-empty-comment"])
+        comment_14: ("<!--->", "", &["abrupt-closing-of-empty-comment"])
+        comment_15: ("<!---->", "", &[])
+        comment_16: ("<!--a--->", "a-", &[])
+        comment_17: ("<!--!>", "!>", &["eof-in-comment"])
+    }
+
+    #[test]
+    fn comment_closing() {
+        for (input, closing) in [
+            ("<!--x-->", CommentClosing::Normal),
+            ("<!--x--!>", CommentClosing::Bang),
+            ("<!-->", CommentClosing::Abrupt),
+            ("<?x>", CommentClosing::Abrupt),
+            // A single '>' does not close a comment, so this runs until the end of the input
+            ("<!--x>", CommentClosing::Normal),
+        ] {
+            let (tokens, _) = tokenize(input, None);
+            assert!(
+                matches!(tokens[0], Token::Comment { closing: c, .. } if c == closing),
+                "for {input:?}: {tokens:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn end_tag_open_recovery() {
+        let comment = |value: &str| Token::Comment {
+            value: value.into(),
+            closing: CommentClosing::Abrupt,
+        };
+        for (input, expected, error) in [
+            // Nothing is emitted for an empty end tag
+            ("</>", Token::Eof, "missing-end-tag-name"),
+            (
+                "</ x>",
+                comment(" x"),
+                "invalid-first-character-of-tag-name",
+            ),
+            ("</1>", comment("1"), "invalid-first-character-of-tag-name"),
+            (
+                "</!x>",
+                comment("!x"),
+                "invalid-first-character-of-tag-name",
+            ),
+            ("</", Token::Text("</".into()), "eof-before-tag-name"),
+        ] {
+            let (tokens, errors) = tokenize(input, None);
+            let token = tokens.into_iter().next().unwrap_or(Token::Eof);
+            assert_eq!(expected, token, "for {input:?}");
+
+            assert_eq!(errors.len(), 1, "for {input:?}");
+            assert_eq!(errors[0].message, error);
+        }
+    }
+
+    #[test]
+    fn bogus_comment_with_invalid_characters() {
+        let body = "a".repeat(1000);
+        let input = format!("<?{body}\0{body}\u{1}{body}\u{FDD0}{body}>");
+
+        let (tokens, errors) = tokenize(&input, None);
+        assert_eq!(
+            tokens,
+            vec![Token::Comment {
+                value: format!("?{body}\u{FFFD}{body}\u{1}{body}\u{FDD0}{body}"),
+                closing: CommentClosing::Abrupt,
+            }]
+        );
+
+        let errors = errors
+            .iter()
+            .map(|e| (e.message.clone(), e.col))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("unexpected-question-mark-instead-of-tag-name".into(), 2),
+                ("unexpected-null-character".into(), 1003),
+                ("control-character-in-input-stream".into(), 2004),
+                ("noncharacter-in-input-stream".into(), 3005),
+            ]
+        );
+
+        // Errors after a newline are reported on the next line
+        let (tokens, errors) = tokenize("<?a\nb\0\u{1}>", None);
+        assert_eq!(
+            tokens[0],
+            Token::Comment {
+                value: "?a\nb\u{FFFD}\u{1}".into(),
+                closing: CommentClosing::Abrupt,
+            }
+        );
+        let errors = errors
+            .iter()
+            .map(|e| (e.message.as_str(), e.line, e.col))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("unexpected-question-mark-instead-of-tag-name", 1, 2),
+                ("unexpected-null-character", 2, 2),
+                ("control-character-in-input-stream", 2, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn rcdata_source() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a &amp; b&copy</x></title>&lt;", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            initial_state: State::RCDATA,
+            last_start_tag: "title".into(),
+            record_rcdata_source: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("a & b©</x>".into()), token);
+        assert_eq!(
+            tokenizer.take_rcdata_source().as_deref(),
+            Some("a &amp; b&copy</x>")
+        );
+        assert!(tokenizer.take_rcdata_source().is_none());
+
+        // Text outside of RCDATA has no source
+        tokenizer.next_token(ParserData::default()).unwrap();
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("<".into()), token);
+        assert!(tokenizer.take_rcdata_source().is_none());
+    }
+
+    #[test]
+    fn identical_inputs_give_equal_tokens() {
+        let input = "<!DOCTYPE html><p a=1 b=2 a=3>x &amp; y<!--c--></p>";
+        assert_eq!(tokenize(input, None), tokenize(input, None));
+
+        // The order of the attribute list is part of the equality
+        let (tokens, _) = tokenize("<p a=1 b=2>", None);
+        let (swapped, _) = tokenize("<p b=2 a=1>", None);
+        assert_ne!(tokens, swapped);
+    }
+
+    #[test]
+    fn in_attribute() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p title=\"a", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            streaming: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+        assert!(!tokenizer.in_attribute());
+
+        // Streaming pauses halfway the quoted attribute value
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+        assert!(tokenizer.in_attribute());
+
+        tokenizer.feed("b\">");
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert!(matches!(token, Token::StartTag { ref name, .. } if name == "p"));
+        assert!(!tokenizer.in_attribute());
+    }
+
+    #[test]
+    fn strict_recover() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("&#;<p>&#;", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            strict: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        let err = tokenizer.next_token(ParserData::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "parse error: absence-of-digits-in-numeric-character-reference at 1:3"
+        );
+        // The tokenizer stays halted until it recovers
+        assert!(tokenizer.next_token(ParserData::default()).is_err());
+
+        tokenizer.recover();
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("&#;".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert!(matches!(token, Token::StartTag { ref name, .. } if name == "p"));
+
+        // The next error halts again
+        assert!(tokenizer.next_token(ParserData::default()).is_err());
+        tokenizer.recover();
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("&#;".into()), token);
+        assert_eq!(tokenizer.get_error_logger().get_errors().len(), 2);
+    }
+
+    #[test]
+    fn token_stats() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<!DOCTYPE html><html><!-- a --><p class=x>Héllo &amp; bye</p><br/><!-- b --></html>",
+            None,
+        );
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let stats = tokenizer.token_stats().unwrap();
+        assert_eq!(
+            stats,
+            TokenStats {
+                start_tags: 3,
+                end_tags: 2,
+                comments: 2,
+                characters: 11,
+                doctypes: 1,
+            }
+        );
+
+        // The stream has been drained
+        assert_eq!(tokenizer.token_stats().unwrap(), TokenStats::default());
+    }
+
+    #[test]
+    fn checkpoint_and_restore() {
+        let input = "<p class=a>x &amp; y<!-- c --></p><b title='&copy'>z&bogus;</b>";
+
+        let mut chars = CharIterator::new();
+        chars.read_from_str(input, None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            record_source_map: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        tokenizer.next_token(ParserData::default()).unwrap();
+        tokenizer.next_token(ParserData::default()).unwrap();
+        let checkpoint = tokenizer.checkpoint();
+
+        let tail = |tokenizer: &mut Tokenizer| {
+            let mut tokens = vec![];
+            loop {
+                let token = tokenizer.next_token(ParserData::default()).unwrap();
+                if token.is_eof() {
+                    break;
+                }
+                tokens.push(token);
+            }
+            (
+                tokens,
+                tokenizer.get_error_logger().get_errors(),
+                tokenizer.source_map().to_vec(),
+            )
+        };
+
+        let (tokens, errors, source_map) = tail(&mut tokenizer);
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(errors.len(), 2);
+
+        // Restoring reproduces the same tokens, errors and spans
+        tokenizer.restore(checkpoint.clone()).unwrap();
+        assert_eq!(tokenizer.get_error_logger().get_errors().len(), 0);
+        assert_eq!(tail(&mut tokenizer), (tokens, errors, source_map));
+
+        // A checkpoint can be restored more than once
+        tokenizer.restore(checkpoint).unwrap();
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(
+            token,
+            Token::Comment {
+                value: " c ".into(),
+                closing: CommentClosing::Normal,
+            }
+        );
+    }
+
+    #[test]
+    fn checkpoint_with_custom_sink_and_insertions() {
+        /// Sink that only counts the errors
+        #[derive(Default)]
+        struct CountingSink {
+            count: usize,
+        }
+
+        impl ParseErrorSink for CountingSink {
+            fn add_error(&mut self, _pos: Position, _message: &str) {
+                self.count += 1;
+            }
+
+            fn mark(&self) -> usize {
+                self.count
+            }
+
+            fn rollback(&mut self, mark: usize) {
+                self.count = mark;
+            }
+        }
+
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p>z", None);
+
+        let sink = Rc::new(RefCell::new(CountingSink::default()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, sink.clone());
+        tokenizer.next_token(ParserData::default()).unwrap();
+
+        // Text inserted after the checkpoint is taken out again on restore
+        let checkpoint = tokenizer.checkpoint();
+        tokenizer.insert_input("a&copy b").unwrap();
+        assert_eq!(
+            tokenizer.next_token(ParserData::default()).unwrap(),
+            Token::Text("a© bz".into())
+        );
+        assert_eq!(sink.borrow().count, 1);
+
+        tokenizer.restore(checkpoint).unwrap();
+        assert_eq!(sink.borrow().count, 0);
+        assert_eq!(
+            tokenizer.next_token(ParserData::default()).unwrap(),
+            Token::Text("z".into())
+        );
+
+        // Text inserted before the checkpoint is kept, and still counts as pending
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p>", None);
+
+        let opts = || Options {
+            max_insertion_depth: 2,
+            ..Default::default()
+        };
+        let sink = Rc::new(RefCell::new(CountingSink::default()));
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts()), sink.clone());
+        tokenizer.insert_input("<b>x").unwrap();
+        tokenizer.next_token(ParserData::default()).unwrap();
+        let checkpoint = tokenizer.checkpoint();
+
+        tokenizer.insert_input("<i>").unwrap();
+        assert!(tokenizer.insert_input("<u>").is_err());
+
+        tokenizer.restore(checkpoint).unwrap();
+        tokenizer.insert_input("<i>").unwrap();
+        assert!(tokenizer.insert_input("<u>").is_err());
+        let mut tokens = vec![];
+        loop {
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            if token.is_eof() {
+                break;
+            }
+            tokens.push(token);
+        }
+        assert_eq!(tokens.len(), 3);
+        assert!(matches!(&tokens[0], Token::StartTag { name, .. } if name == "i"));
+        assert_eq!(tokens[1], Token::Text("x".into()));
+        assert!(matches!(&tokens[2], Token::StartTag { name, .. } if name == "p"));
+    }
+
+    #[test]
+    fn extract_scripts() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(
+            "<p>a</p><script>if (a < b) { x(\"</p>\"); }</script>\n<title><script></title>\
+             <script type=\"module\" src=x>\n  import y;\n</script>",
+            None,
+        );
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let scripts = tokenizer.extract_scripts().unwrap();
+        assert_eq!(scripts.len(), 2);
+
+        assert!(scripts[0].attributes.is_empty());
+        assert_eq!(scripts[0].body, "if (a < b) { x(\"</p>\"); }");
+        assert_eq!(scripts[0].span.start, Position::new(16, 1, 17));
+        assert_eq!(scripts[0].span.end, Position::new(41, 1, 42));
+
+        assert_eq!(
+            scripts[1].attributes,
+            vec![Attribute::new("type", "module"), Attribute::new("src", "x")]
+        );
+        assert_eq!(scripts[1].body, "\n  import y;\n");
+        assert_eq!(scripts[1].span.start.line, 2);
+        assert_eq!(scripts[1].span.end, Position::new(115, 4, 1));
+
+        // Spans are only kept when the tokenizer records them itself
+        assert!(tokenizer.source_map().is_empty());
+    }
+
+    #[test]
+    fn extract_scripts_after_queued_tokens() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a<p><script>x</script>", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        // The <p> start tag is still queued after the text has been returned
+        assert_eq!(
+            tokenizer.next_token(ParserData::default()).unwrap(),
+            Token::Text("a".into())
+        );
+
+        let scripts = tokenizer.extract_scripts().unwrap();
+        assert_eq!(scripts.len(), 1);
+        assert_eq!(scripts[0].body, "x");
+        assert_eq!(scripts[0].span.start, Position::new(12, 1, 13));
+        assert_eq!(scripts[0].span.end, Position::new(13, 1, 14));
+    }
+
+    #[test]
+    fn doctype_whitespace() {
+        for input in [
+            "<!DOCTYPE\t\u{000C}html>",
+            "<!DOCTYPE\u{000C}\thtml\t\u{000C}>",
+            "<!DOCTYPE \t\n\u{000C}HTML\u{000C} >",
+        ] {
+            let (tokens, errors) = tokenize(input, None);
+            assert_eq!(
+                vec![Token::DocType {
+                    name: Some("html".into()),
+                    force_quirks: false,
+                    pub_identifier: None,
+                    sys_identifier: None,
+                }],
+                tokens,
+                "for {input:?}"
+            );
+            assert!(errors.is_empty());
+        }
+    }
+
+    #[test]
+    fn doctype_identifiers() {
+        for (input, public, system, force_quirks, errors) in [
+            (
+                r#"<!DOCTYPE html PUBLIC "x" "y">"#,
+                Some("x"),
+                Some("y"),
+                false,
+                vec![],
+            ),
+            (
+                "<!DOCTYPE html PUBLIC 'x' 'y'>",
+                Some("x"),
+                Some("y"),
+                false,
+                vec![],
+            ),
+            (
+                r#"<!DOCTYPE html PUBLIC 'x' "y">"#,
+                Some("x"),
+                Some("y"),
+                false,
+                vec![],
+            ),
+            ("<!DOCTYPE html PUBLIC 'x'>", Some("x"), None, false, vec![]),
+            ("<!DOCTYPE html SYSTEM 'y'>", None, Some("y"), false, vec![]),
+            (
+                r#"<!DOCTYPE html SYSTEM "y">"#,
+                None,
+                Some("y"),
+                false,
+                vec![],
+            ),
+            // Quotes of the other kind are part of the identifier
+            (
+                r#"<!DOCTYPE html PUBLIC "x'x">"#,
+                Some("x'x"),
+                None,
+                false,
+                vec![],
+            ),
+            (
+                "<!DOCTYPE html PUBLIC x>",
+                None,
+                None,
+                true,
+                vec!["missing-quote-before-doctype-public-identifier"],
+            ),
+            (
+                "<!DOCTYPE html SYSTEM y>",
+                None,
+                None,
+                true,
+                vec!["missing-quote-before-doctype-system-identifier"],
+            ),
+            (
+                "<!DOCTYPE html PUBLIC 'x' y>",
+                Some("x"),
+                None,
+                true,
+                vec!["missing-quote-before-doctype-system-identifier"],
+            ),
+            (
+                "<!DOCTYPE html PUBLIC'x'>",
+                Some("x"),
+                None,
+                false,
+                vec!["missing-whitespace-after-doctype-public-keyword"],
+            ),
+            (
+                "<!DOCTYPE html PUBLIC 'x''y'>",
+                Some("x"),
+                Some("y"),
+                false,
+                vec!["missing-whitespace-between-doctype-public-and-system-identifiers"],
+            ),
+            (
+                "<!DOCTYPE html PUBLIC 'x>",
+                Some("x"),
+                None,
+                true,
+                vec!["abrupt-doctype-public-identifier"],
+            ),
+        ] {
+            let (tokens, logged) = tokenize(input, None);
+            assert_eq!(
+                vec![Token::DocType {
+                    name: Some("html".into()),
+                    force_quirks,
+                    pub_identifier: public.map(Into::into),
+                    sys_identifier: system.map(Into::into),
+                }],
+                tokens,
+                "for {input:?}"
+            );
+
+            let logged: Vec<String> = logged.into_iter().map(|e| e.message).collect();
+            assert_eq!(logged, errors, "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn unpaired_utf16_surrogate() {
+        let mut chars = CharIterator::new();
+        chars.read_from_utf16(&[0x61, 0xD800, 0x62]);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("a\u{FFFD}b".into()), token);
+
+        // The surrogate has been replaced while decoding, so the tokenizer never sees it
+        assert!(tokenizer.get_error_logger().get_errors().is_empty());
+    }
+
+    #[test]
+    fn leading_bom() {
+        let mut chars = CharIterator::new();
+        chars.set_strip_leading_bom(true);
+        chars.read_from_str("\u{FEFF}<p>", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert!(matches!(token, Token::StartTag { ref name, .. } if name == "p"));
+
+        // Without the option, the BOM is a character like any other
+        let (tokens, _) = tokenize("\u{FEFF}<p>", None);
+        assert_eq!(tokens[0], Token::Text("\u{FEFF}".into()));
+    }
+
+    macro_rules! attribute_tests {
+        ($($name:ident : $value:expr)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (input, attr_name, attr_value, errors): (&str, &str, &str, &[&str]) =
+                        $value;
+
+                    let (tokens, logged) = tokenize(input, None);
+
+                    // The delimiter must have terminated the attribute, so the tag is the only
+                    // token
+                    let [tag @ Token::StartTag { attributes, .. }] = tokens.as_slice() else {
+                        panic!("expected a single start tag, got {tokens:?}");
+                    };
+                    assert_eq!(attributes.len(), 1);
+                    assert_eq!(tag.attr(attr_name), Some(attr_value));
+
+                    let logged: Vec<String> = logged.into_iter().map(|e| e.message).collect();
+                    assert_eq!(logged, errors);
+                }
+            )*
+        }
+    }
+
+    #[cfg(feature = "log_parse_errors")]
+    #[test]
+    fn log_parse_errors() {
+        testing_logger::setup();
+
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p a=1 a=2>", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+        tokenizer.next_token(ParserData::default()).unwrap();
+
+        testing_logger::validate(|captured_logs| {
+            assert_eq!(captured_logs.len(), 1);
+            assert_eq!(captured_logs[0].level, log::Level::Warn);
+            assert_eq!(
+                captured_logs[0].body,
+                "parse error at 1:9: duplicate-attribute"
+            );
+        });
+    }
+
+    #[cfg(feature = "named_entities")]
+    #[test]
+    fn named_entity_table() {
+        let mut count = 0;
+        let mut copy = None;
+        for (name, chars) in named_entities() {
+            count += 1;
+            if name == "copy" {
+                copy = Some(chars);
+            }
+        }
+
+        assert!(count > 2000);
+        assert_eq!(copy, Some("©"));
+    }
+
+    #[test]
+    fn cdata_in_html_content() {
+        for (cdata_as_text, expected) in [
+            (
+                false,
+                Token::Comment {
+                    value: "[CDATA[x]]".into(),
+                    closing: CommentClosing::Abrupt,
+                },
+            ),
+            (true, Token::Text("x".into())),
+        ] {
+            let opts = Options {
+                cdata_as_text,
+                ..Default::default()
+            };
+            let (tokens, errors) = tokenize("<![CDATA[x]]>", Some(opts));
+            assert_eq!(vec![expected], tokens);
+            assert_eq!(!cdata_as_text, errors.len() == 1);
+        }
+    }
+
+    #[test]
+    fn processing_instruction() {
+        for (parse_mode, expected) in [
+            (
+                ParseMode::Html,
+                Token::Comment {
+                    value: "?xml version=\"1.0\"?".into(),
+                    closing: CommentClosing::Abrupt,
+                },
+            ),
+            (
+                ParseMode::XmlLike,
+                Token::ProcessingInstruction {
+                    target: "xml".into(),
+                    data: "version=\"1.0\"".into(),
+                },
+            ),
+        ] {
+            let opts = Options {
+                parse_mode,
+                ..Default::default()
+            };
+            let (tokens, errors) = tokenize("<?xml version=\"1.0\"?><p>", Some(opts));
+            assert_eq!(expected, tokens[0]);
+            assert!(matches!(&tokens[1], Token::StartTag { name, .. } if name == "p"));
+            assert_eq!(parse_mode == ParseMode::Html, errors.len() == 1);
+        }
+    }
+
+    #[test]
+    fn processing_instruction_eof() {
+        let opts = Options {
+            parse_mode: ParseMode::XmlLike,
+            ..Default::default()
+        };
+        let (tokens, errors) = tokenize("<?php echo 1; ?", Some(opts));
+        assert_eq!(
+            vec![Token::ProcessingInstruction {
+                target: "php".into(),
+                data: "echo 1; ?".into(),
+            }],
+            tokens
+        );
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn streaming_entity() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a&cop", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            streaming: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("a".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+
+        tokenizer.feed("y;b");
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("©b".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+        assert!(tokenizer.get_error_logger().get_errors().is_empty());
+    }
+
+    #[test]
+    fn streaming_finish() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a&cop", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = || Options {
+            streaming: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts()), error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("a".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+
+        // Without more input, the pending reference is resolved at the end of the input
+        tokenizer.finish();
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("&cop".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+        assert!(tokenizer.get_error_logger().get_errors().is_empty());
+
+        // A legacy reference without semicolon could still have been longer
+        let mut chars = CharIterator::new();
+        chars.read_from_str("&copy", None);
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts()), error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+
+        tokenizer.finish();
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("©".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+        assert_eq!(
+            tokenizer.get_error_logger().get_errors()[0].message,
+            "missing-semicolon-after-character-reference"
+        );
+    }
+
+    #[test]
+    fn streaming_ampersand_at_chunk_end() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("&", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            streaming: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+
+        tokenizer.feed("amp;");
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("&".into()), token);
+    }
+
+    #[test]
+    fn streaming_attribute_value_entity() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<a title=\"a&am", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            streaming: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+
+        tokenizer.feed("p;b\" alt=b&amp");
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+
+        tokenizer.feed(";c>");
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        let Token::StartTag {
+            name, attributes, ..
+        } = token
+        else {
+            panic!("expected a start tag, got {token:?}");
+        };
+        assert_eq!(name, "a");
+        let values: Vec<_> = attributes
+            .iter()
+            .map(|attr| (attr.name.as_str(), attr.value.as_str()))
+            .collect();
+        assert_eq!(values, vec![("title", "a&b"), ("alt", "b&c")]);
+        assert!(tokenizer.get_error_logger().get_errors().is_empty());
+    }
+
+    #[test]
+    fn streaming_attribute_value_at_end_of_input() {
+        for input in ["<a title=\"a&am", "<a title='a&am", "<a title=a&am"] {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(input, None);
+
+            let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+            let opts = Options {
+                streaming: true,
+                ..Default::default()
+            };
+            let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            assert_eq!(Token::Eof, token, "for {input:?}");
+            assert!(tokenizer.in_attribute(), "for {input:?}");
+
+            // At the real end of the input, the tag is cut off like in a regular stream
+            tokenizer.finish();
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            assert_eq!(Token::Eof, token, "for {input:?}");
+            assert!(!tokenizer.in_attribute(), "for {input:?}");
+
+            let errors = tokenizer.get_error_logger().get_errors();
+            assert_eq!(errors.len(), 1, "for {input:?}");
+            assert_eq!(errors[0].message, "eof-in-tag", "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn lending_token_stream() {
+        let input = "<B>bold</B><i>italic</i>";
+        let mut chars = CharIterator::new();
+        chars.set_track_byte_offsets(true);
+        chars.read_from_str(input, None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let mut output = String::new();
+        let mut raw = vec![];
+        let mut stream = tokenizer.lending_token_stream();
+        while let Some(spanned) = stream.next_token().unwrap() {
+            raw.push(spanned.raw_source(input).unwrap());
+            match &mut spanned.token {
+                Token::StartTag { name, .. } | Token::EndTag { name, .. } if name == "b" => {
+                    *name = "strong".into();
+                }
+                _ => {}
+            }
+            output.push_str(&spanned.token.to_string());
+        }
+
+        assert_eq!(output, "<strong>bold</strong><i>italic</i>");
+        assert_eq!(raw, ["<B>", "bold", "</B>", "<i>", "italic", "</i>"]);
+    }
+
+    #[test]
+    fn inspect_consume_buffer() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("&#169;&#x", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        tokenizer.consume('a');
+        assert_eq!(tokenizer.get_consumed_str(), "a");
+
+        // Read the ampersand, as the data state would have done
+        tokenizer.chars.read_char();
+        tokenizer.consume_character_reference(None, false);
+        assert_eq!(tokenizer.get_consumed_str(), "a©");
+
+        // An unfinished numeric reference is flushed as-is
+        tokenizer.chars.read_char();
+        tokenizer.consume_character_reference(None, false);
+        assert_eq!(tokenizer.get_consumed_str(), "a©&#x");
+
+        // Inspecting does not clear the buffer
+        assert!(tokenizer.has_consumed_data());
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("a©&#x".into()), token);
+    }
+
+    #[test]
+    fn duplicate_attributes() {
+        for preserve_duplicate_attributes in [false, true] {
+            let opts = Options {
+                preserve_duplicate_attributes,
+                ..Default::default()
+            };
+            let (tokens, errors) = tokenize("<a x=1 y=3 x=2>", Some(opts));
+            let Token::StartTag { attributes, .. } = &tokens[0] else {
+                panic!("expected a start tag, got {tokens:?}");
+            };
+
+            // The first occurrence wins, as per spec
+            let map = tokens[0].attr_map();
+            assert_eq!(map.len(), 2);
+            assert_eq!(map.get("x").unwrap(), "1");
+
+            // The attributes are in source order, and only hold the duplicates when asked
+            let found: Vec<_> = attributes
+                .iter()
+                .map(|attr| (attr.name.as_str(), attr.value.as_str()))
+                .collect();
+            if preserve_duplicate_attributes {
+                assert_eq!(found, vec![("x", "1"), ("y", "3"), ("x", "2")]);
+            } else {
+                assert_eq!(found, vec![("x", "1"), ("y", "3")]);
+            }
+
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].message, "duplicate-attribute");
+        }
+    }
+
+    #[test]
+    fn raw_attribute_values() {
+        let (tokens, _) = tokenize(
+            "<a href=\"&#x6a;avascript:alert(1)\" title='a &amp; b' x=&lt;y z>",
+            None,
+        );
+        let Token::StartTag { attributes, .. } = &tokens[0] else {
+            panic!("expected a start tag, got {tokens:?}");
+        };
+
+        let found: Vec<_> = attributes
+            .iter()
+            .map(|a| (a.name.as_str(), a.value.as_str(), a.raw_value.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("href", "javascript:alert(1)", "&#x6a;avascript:alert(1)"),
+                ("title", "a & b", "a &amp; b"),
+                ("x", "<y", "&lt;y"),
+                ("z", "", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn eof_is_repeated() {
+        for input in [
+            "",
+            "text",
+            "<a",
+            "<a href='x",
+            "<!-- comment",
+            "<!DOCTYPE",
+            "&amp",
+        ] {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(input, None);
+
+            let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+            let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+            let mut tokens = 0;
+            while tokenizer.next_token(ParserData::default()).unwrap() != Token::Eof {
+                tokens += 1;
+                assert!(tokens < 10, "no eof found for {input:?}");
+            }
+
+            // Draining past the end keeps on returning EOF
+            for _ in 0..3 {
+                let token = tokenizer.next_token(ParserData::default()).unwrap();
+                assert_eq!(Token::Eof, token, "for {input:?}");
+            }
+
+            let mut stream = tokenizer.lending_token_stream();
+            assert!(stream.next_token().unwrap().is_none());
+            assert!(stream.next_token().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn read_from_chars() {
+        let mut chars = CharIterator::new();
+        let input: Vec<char> = "&amp;".chars().collect();
+        chars.read_from_chars(&input);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("&".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+    }
+
+    #[test]
+    fn source_map() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p>\n  a &lt; b\n</p>\n<!-- c -->", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            record_source_map: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        let mut tokens = vec![];
+        loop {
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            if token == Token::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+        assert_eq!(tokens.len(), 5);
+
+        let spans: Vec<_> = tokenizer
+            .source_map()
+            .iter()
+            .map(|s| ((s.start.line, s.start.col), (s.end.line, s.end.col)))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ((1, 1), (1, 4)),   // <p>
+                ((1, 4), (3, 1)),   // \n  a < b\n
+                ((3, 1), (3, 5)),   // </p>
+                ((3, 5), (4, 1)),   // \n
+                ((4, 1), (4, 11)),  // <!-- c -->
+                ((4, 11), (4, 11)), // EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn script_data_double_escaped() {
+        for (input, body) in [
+            (
+                "<!--<script>x</script>--></script>",
+                "<!--<script>x</script>-->",
+            ),
+            (
+                "<!--<SCRIPT>x</Script>--></script>",
+                "<!--<SCRIPT>x</Script>-->",
+            ),
+            // Without the double escape, the first end tag closes the script
+            ("<!--x--></script>", "<!--x-->"),
+            ("<!--<scripts>x</script>-->", "<!--<scripts>x"),
+            // Only "-->" leaves the escaped state, "- ->" does not
+            (
+                "<!--<script>- -></script>x</script>",
+                "<!--<script>- -></script>x",
+            ),
+        ] {
+            let opts = Options {
+                initial_state: State::ScriptData,
+                last_start_tag: "script".into(),
+                ..Default::default()
+            };
+            let (tokens, _) = tokenize(input, Some(opts));
+            assert_eq!(Token::Text(body.into()), tokens[0], "for {input:?}");
+            assert!(
+                matches!(&tokens[1], Token::EndTag { name, .. } if name == "script"),
+                "for {input:?}: {tokens:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn null_characters_in_text() {
+        for (state, input, expected) in [
+            // The data state keeps the null, while the other text states replace it
+            (State::Data, "a\0b\0", "a\0b\0"),
+            (State::RAWTEXT, "a\0b\0", "a\u{FFFD}b\u{FFFD}"),
+            (State::RCDATA, "a\0b\0", "a\u{FFFD}b\u{FFFD}"),
+            (State::ScriptData, "a\0b\0", "a\u{FFFD}b\u{FFFD}"),
+        ] {
+            let opts = Options {
+                initial_state: state,
+                ..Default::default()
+            };
+            let (tokens, errors) = tokenize(input, Some(opts));
+            assert_eq!(vec![Token::Text(expected.into())], tokens, "for {state:?}");
+
+            assert_eq!(errors.len(), 2, "for {state:?}");
+            assert!(errors
+                .iter()
+                .all(|e| e.message == ParserError::UnexpectedNullCharacter.as_str()));
+            assert_eq!(errors[0].col, 2);
+            assert_eq!(errors[1].col, 4);
+        }
+    }
+
+    #[test]
+    fn null_character_in_tag_name() {
+        for input in ["<a\0b>", "</a\0b>"] {
+            let (tokens, errors) = tokenize(input, None);
+
+            // The null is replaced, and does not end the tag name
+            assert!(
+                matches!(
+                    tokens.as_slice(),
+                    [Token::StartTag { name, .. } | Token::EndTag { name, .. }] if name == "a\u{FFFD}b"
+                ),
+                "for {input:?}: {tokens:?}"
+            );
+
+            assert_eq!(errors.len(), 1, "for {input:?}");
+            assert_eq!(
+                errors[0].message,
+                ParserError::UnexpectedNullCharacter.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn rcdata_less_than_sign() {
+        for (input, expected) in [
+            ("a<b>c</title>", "a<b>c"),
+            ("a</b>c</title>", "a</b>c"),
+            ("a<</title>", "a<"),
+            ("a</ b</title>", "a</ b"),
+            ("a</titles>b</title>", "a</titles>b"),
+            ("a</TITLE>", "a"),
+        ] {
+            let opts = Options {
+                initial_state: State::RCDATA,
+                last_start_tag: "title".into(),
+                ..Default::default()
+            };
+            let (tokens, errors) = tokenize(input, Some(opts));
+
+            // Anything but the matching end tag is text
+            assert_eq!(Token::Text(expected.into()), tokens[0], "for {input:?}");
+            assert!(
+                matches!(&tokens[1], Token::EndTag { name, .. } if name == "title"),
+                "for {input:?}: {tokens:?}"
+            );
+            assert!(errors.is_empty());
+        }
+    }
+
+    #[test]
+    fn warn_gt_in_attribute_value() {
+        for (enabled, strict) in [(false, false), (true, false), (true, true)] {
+            let opts = Options {
+                warn_gt_in_attribute_value: enabled,
+                strict,
+                ..Default::default()
+            };
+
+            // The value is unchanged, and strict mode is not halted by the diagnostic
+            let (token, errors, diagnostics) = with_tokenizer(
+                "<p title=\"a>b\" alt='>' class=c>",
+                Some(opts),
+                |tokenizer| {
+                    let token = tokenizer.next_token(ParserData::default()).unwrap();
+                    let logger = tokenizer.get_error_logger();
+                    (token, logger.get_errors(), logger.get_diagnostics())
+                },
+            );
+            assert_eq!(token.attr("title"), Some("a>b"));
+            assert_eq!(token.attr("alt"), Some(">"));
+
+            // Diagnostics are not parse errors
+            assert!(errors.is_empty());
+            if enabled {
+                assert_eq!(diagnostics.len(), 2);
+                assert_eq!(
+                    diagnostics[0].message,
+                    "greater-than-sign-in-attribute-value"
+                );
+                assert_eq!((diagnostics[0].line, diagnostics[0].col), (1, 12));
+                assert_eq!((diagnostics[1].line, diagnostics[1].col), (1, 21));
+            } else {
+                assert!(diagnostics.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("text", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::with_capacity(&mut chars, None, error_logger.clone(), 4096);
+
+        assert!(tokenizer.consumed.capacity() >= 4096);
+
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("text".into()), token);
+        assert!(tokenizer.consumed.capacity() >= 4096);
+    }
+
+    #[test]
+    fn bare_ampersand() {
+        for (input, expected) in [("&", "&"), ("&;", "&;"), ("x&", "x&"), ("&&", "&&")] {
+            let (tokens, errors) = tokenize(input, None);
+            assert_eq!(vec![Token::Text(expected.into())], tokens, "for {input:?}");
+            assert!(errors.is_empty());
+        }
+    }
+
+    #[test]
+    fn missing_whitespace_between_attributes() {
+        let (tokens, errors) = tokenize(r#"<a x="1"y='2'>"#, None);
+        let Token::StartTag { attributes, .. } = &tokens[0] else {
+            panic!("expected a start tag, got {tokens:?}");
+        };
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(tokens[0].attr("x"), Some("1"));
+        assert_eq!(tokens[0].attr("y"), Some("2"));
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "missing-whitespace-between-attributes");
+    }
+
+    #[test]
+    fn insert_input() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            streaming: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        // The reference is incomplete, so tokenizing stops in the middle of it
+        tokenizer.feed("&co");
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+
+        // Inserted text continues the reference where tokenizing stopped
+        tokenizer.insert_input("py;").unwrap();
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Text("©".into()), token);
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        assert_eq!(Token::Eof, token);
+        assert!(tokenizer.get_error_logger().get_errors().is_empty());
+    }
+
+    #[test]
+    fn byte_offsets_of_tokens() {
+        let input = "<p>a\r\nb</p>\r\n<!-- é -->";
+
+        let mut chars = CharIterator::new();
+        chars.set_track_byte_offsets(true);
+        chars.read_from_str(input, None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            record_source_map: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+        while tokenizer.next_token(ParserData::default()).unwrap() != Token::Eof {}
+
+        let ranges: Vec<_> = tokenizer
+            .source_map()
+            .iter()
+            .map(|span| tokenizer.chars.byte_range(span).unwrap())
+            .collect();
+
+        // The byte ranges point into the original input, including the CRLFs. The final (empty)
+        // range belongs to the Eof token.
+        let found: Vec<_> = ranges.iter().map(|r| &input[r.clone()]).collect();
+        assert_eq!(
+            found,
+            vec!["<p>", "a\r\nb", "</p>", "\r\n", "<!-- é -->", ""]
+        );
+        assert_eq!(ranges.last().unwrap(), &(input.len()..input.len()));
+    }
+
+    #[test]
+    fn raw_source_of_tokens() {
+        let input = "<a href=\"x\">a&amp;b<A HREF=\"x\" title=a&amp;b>T&eacute;xt\r\n</a >";
+
+        let mut chars = CharIterator::new();
+        chars.set_track_byte_offsets(true);
+        chars.read_from_str(input, None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let first = tokenizer.next_spanned_token(ParserData::default()).unwrap();
+        assert_eq!(first.raw_source(input), Some("<a href=\"x\">"));
+
+        // Copying every token through untouched reproduces the input, text included
+        let mut output = first.raw_source(input).unwrap().to_string();
+        let mut texts = vec![];
+        loop {
+            let spanned = tokenizer.next_spanned_token(ParserData::default()).unwrap();
+            let raw = spanned.raw_source(input).unwrap();
+            output.push_str(raw);
+            match spanned.token {
+                Token::Eof => break,
+                Token::Text(text) => texts.push((text, raw)),
+                _ => {}
+            }
+        }
+        assert_eq!(output, input);
+        assert_eq!(
+            texts,
+            vec![
+                ("a&b".into(), "a&amp;b"),
+                ("Téxt\n".into(), "T&eacute;xt\r\n")
+            ]
+        );
+
+        // Without byte offsets, tokens cannot be mapped onto the input
+        let mut chars = CharIterator::new();
+        chars.read_from_str(input, None);
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger);
+        let first = tokenizer.next_spanned_token(ParserData::default()).unwrap();
+        assert_eq!(first.raw_source(input), None);
+    }
+
+    #[test]
+    fn tokenize_reader_chunks() {
+        let input = "\u{FEFF}<p title='é &amp; ü'>a\r\nb &copy; 😀</p>\r<!-- x -->\r\n<b>c\r";
+
+        let mut chars = CharIterator::new();
+        chars.read_from_bytes(input.as_bytes(), None).unwrap();
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let mut expected = vec![];
+        loop {
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            expected.push(token.clone());
+            if token.is_eof() {
+                break;
+            }
+        }
+
+        // Chunks split multibyte characters, references and CRLFs in every possible way
+        for chunk_size in 1..=8 {
+            let mut chars = CharIterator::new();
+            chars.read_from_reader(input.as_bytes(), chunk_size, None);
+            let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+            let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+            let mut tokens = vec![];
+            loop {
+                let token = tokenizer.next_token(ParserData::default()).unwrap();
+                tokens.push(token.clone());
+                if token.is_eof() {
+                    break;
+                }
+            }
+            assert_eq!(tokens, expected, "for chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn tokenize_reader_bounded_buffer() {
+        /// Reader that generates the same snippet over and over again
+        struct Repeat {
+            snippet: &'static [u8],
+            remaining: usize,
+            pos: usize,
+        }
+
+        impl Read for Repeat {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let mut len = 0;
+                while len < buf.len() && self.remaining > 0 {
+                    buf[len] = self.snippet[self.pos];
+                    len += 1;
+                    self.pos += 1;
+                    if self.pos == self.snippet.len() {
+                        self.pos = 0;
+                        self.remaining -= 1;
+                    }
+                }
+                Ok(len)
+            }
+        }
+
+        let snippet = "<p class=\"x\">hello &amp; wörld</p>\r\n";
+        let reader = Repeat {
+            snippet: snippet.as_bytes(),
+            remaining: 20_000,
+            pos: 0,
+        };
+
+        let mut chars = CharIterator::new();
+        chars.read_from_reader(reader, 4096, None);
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let mut start_tags = 0;
+        let mut peak = 0;
+        loop {
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            if token.is_eof() {
+                break;
+            }
+            if matches!(token, Token::StartTag { .. }) {
+                start_tags += 1;
+            }
+            peak = peak.max(tokenizer.chars.buffered_len());
+        }
+
+        assert_eq!(start_tags, 20_000);
+        assert!(tokenizer.chars.length > 600_000);
+        assert!(peak < 3 * 4096, "buffer grew to {peak} characters");
+        assert_eq!(tokenizer.chars.position.line, 20_001);
+        assert!(tokenizer.chars.take_read_error().is_none());
+    }
+
+    #[test]
+    fn read_rcdata_text() {
+        for (input, name, expected, next) in [
+            ("<title>a &amp; b</title>", "title", "a & b", Token::Eof),
+            (
+                "<textarea><b>&lt;</b></textarea>x",
+                "textarea",
+                "<b><</b>",
+                Token::Text("x".into()),
+            ),
+            (
+                "<title>a</titles></title>",
+                "title",
+                "a</titles>",
+                Token::Eof,
+            ),
+            // Without an end tag, the body runs until the end of the input
+            ("<title>a &copy; b", "title", "a © b", Token::Eof),
+        ] {
+            let mut chars = CharIterator::new();
+            chars.read_from_str(input, None);
+
+            let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+            let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            assert!(matches!(token, Token::StartTag { .. }), "for {input:?}");
+
+            let text = tokenizer.read_rcdata_text(name).unwrap();
+            assert_eq!(text, expected, "for {input:?}");
+
+            // Tokenizing continues in the data state after the end tag
+            let token = tokenizer.next_token(ParserData::default()).unwrap();
+            assert_eq!(token, next, "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn custom_error_sink() {
+        /// Sink that only counts the errors per code
+        #[derive(Default)]
+        struct CountingSink {
+            counts: HashMap<String, usize>,
+        }
+
+        impl ParseErrorSink for CountingSink {
+            fn add_error(&mut self, _pos: Position, message: &str) {
+                *self.counts.entry(message.to_string()).or_default() += 1;
+            }
+        }
+
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<a b c b=1 b=2>&copy &not x</a/>", None);
+
+        let sink = Rc::new(RefCell::new(CountingSink::default()));
+        let mut tokenizer = Tokenizer::new(&mut chars, None, sink.clone());
+        while tokenizer.next_token(ParserData::default()).unwrap() != Token::Eof {}
+
+        let counts = &sink.borrow().counts;
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["duplicate-attribute"], 2);
+        assert_eq!(counts["missing-semicolon-after-character-reference"], 2);
+        assert_eq!(counts["end-tag-with-trailing-solidus"], 1);
+
+        // Nothing is stored by this sink
+        assert!(tokenizer.get_error_logger().get_errors().is_empty());
+    }
+
+    #[test]
+    fn custom_error_sink_in_strict_mode() {
+        /// Sink that drops all errors
+        struct NullSink;
+
+        impl ParseErrorSink for NullSink {
+            fn add_error(&mut self, _pos: Position, _message: &str) {}
+        }
+
+        let mut chars = CharIterator::new();
+        chars.read_from_str("a&copy b", None);
+
+        let opts = Options {
+            strict: true,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), Rc::new(RefCell::new(NullSink)));
+
+        // Strict mode still halts, regardless of what the sink does with the error
+        assert!(tokenizer.next_token(ParserData::default()).is_err());
+        tokenizer.recover();
+        assert_eq!(
+            Token::Text("a© b".into()),
+            tokenizer.next_token(ParserData::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn insert_input_nesting_limit() {
+        let mut chars = CharIterator::new();
+        chars.read_from_str("<p>", None);
+
+        let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+        let opts = Options {
+            max_insertion_depth: 3,
+            ..Default::default()
+        };
+        let mut tokenizer = Tokenizer::new(&mut chars, Some(opts), error_logger.clone());
+
+        // Each insertion is made while reading the previous one, like a script that writes a
+        // script that writes.
+        for _ in 0..3 {
+            tokenizer.insert_input("<script>").unwrap();
+            tokenizer.chars.read_char();
+        }
+        assert!(matches!(
+            tokenizer.insert_input("<script>"),
+            Err(Error::Parse(_))
+        ));
+
+        // Once the inserted text has been consumed, inserting is possible again
+        tokenizer.chars.skip(3 * 8);
+        tokenizer.insert_input("a").unwrap();
+        tokenizer.chars.skip(1);
+        tokenizer.insert_input("b").unwrap();
+
+        // A limit far beyond any reasonable nesting fails cleanly as well
+        let mut chars = CharIterator::new();
+        chars.read_from_str("", None);
+        let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+
+        let mut depth = 0;
+        while tokenizer
+            .insert_input("<script>document.write()</script>")
+            .is_ok()
+        {
+            tokenizer.chars.read_char();
+            depth += 1;
+        }
+        assert_eq!(depth, DEFAULT_MAX_INSERTION_DEPTH);
+    }
+
+    attribute_tests! {
+        // Ampersand directly followed by the attribute value delimiter
+        attribute_0: (r#"<a title="a&">"#, "title", "a&", &[])
+        attribute_1: ("<a title='a&'>", "title", "a&", &[])
+        attribute_2: ("<a title=a&>", "title", "a&", &[])
+        attribute_3: (r#"<a title="&">"#, "title", "&", &[])
+
+        // Quotes and less-than signs are part of the attribute name, but raise a parse error
+        attribute_4: (r#"<a a"b=1>"#, r#"a"b"#, "1", &["unexpected-character-in-attribute-name"])
+        attribute_5: ("<a a'b=1>", "a'b", "1", &["unexpected-character-in-attribute-name"])
+        attribute_6: ("<a a<b=1>", "a<b", "1", &["unexpected-character-in-attribute-name"])
+        // An equals sign ends the name, except as first character where it starts the name
+        attribute_7: ("<a a=b=1>", "a", "b=1", &["unexpected-character-in-unquoted-attribute-value"])
+        attribute_8: ("<a =b=1>", "=b", "1", &["unexpected-equals-sign-before-attribute-name"])
+
+        // Disallowed characters in unquoted values raise a parse error, but are kept
+        attribute_9: (r#"<a x=a"b>"#, "x", r#"a"b"#, &["unexpected-character-in-unquoted-attribute-value"])
+        attribute_10: ("<a x=a'b>", "x", "a'b", &["unexpected-character-in-unquoted-attribute-value"])
+        attribute_11: ("<a x=a<b>", "x", "a<b", &["unexpected-character-in-unquoted-attribute-value"])
+        attribute_12: ("<a x=a=b>", "x", "a=b", &["unexpected-character-in-unquoted-attribute-value"])
+        attribute_13: ("<a x=a`b>", "x", "a`b", &["unexpected-character-in-unquoted-attribute-value"])
+        // Whitespace and '>' terminate an unquoted value
+        attribute_14: ("<a x=ab >", "x", "ab", &[])
+        attribute_15: ("<a x=ab\t>", "x", "ab", &[])
+        attribute_16: ("<a x=ab\n>", "x", "ab", &[])
+        attribute_17: ("<a x=ab\u{c}>", "x", "ab", &[])
+        attribute_18: ("<a x=ab>", "x", "ab", &[])
+        // Attribute values are never whitespace normalized, unlike in XML
+        attribute_19: ("<a title=\"  a  b  \">", "title", "  a  b  ", &[])
+        attribute_20: ("<a title='\ta\n\n b\t'>", "title", "\ta\n\n b\t", &[])
+        attribute_21: ("<a title=\" a\r\nb\r\">", "title", " a\nb\n", &[])
+        attribute_22: ("<a title=\"&#32;a&nbsp;\">", "title", " a\u{a0}", &[])
+    }
+}
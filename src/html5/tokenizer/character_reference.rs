@@ -1,10 +1,13 @@
 extern crate lazy_static;
 
 use crate::bytes::Bytes::{self, *};
-use crate::html5::error_logger::ParserError;
-use crate::html5::tokenizer::replacement_tables::{TOKEN_NAMED_CHARS, TOKEN_REPLACEMENTS};
-use crate::html5::tokenizer::{Tokenizer, CHAR_REPLACEMENT};
+use crate::bytes::{CharIterator, Position, Span};
+use crate::html5::error_logger::{ErrorLogger, ParserError};
+use crate::html5::tokenizer::replacement_tables::{c1_replacement, TOKEN_NAMED_CHARS};
+use crate::html5::tokenizer::{Tokenizer, CHAR_CR, CHAR_REPLACEMENT};
 use lazy_static::lazy_static;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 /// Different states for the character references
 pub enum CcrState {
@@ -19,18 +22,302 @@ pub enum CcrState {
     NumericalCharacterReferenceEnd,
 }
 
+/// Result of consuming a character reference
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CharacterReference {
+    /// Characters that have been flushed into the consume buffer (or attribute value)
+    pub text: String,
+    /// True when the reference was terminated by a semicolon
+    pub had_semicolon: bool,
+    /// True when the reference resolved to a named or numeric character reference
+    pub was_entity: bool,
+    /// True when the tokenizer is streaming and the input ended halfway a possible reference.
+    /// Nothing has been flushed and the stream is rewound to the `&`.
+    pub need_more_input: bool,
+}
+
+/// The kind of character reference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntityKind {
+    /// Named reference, like `&copy;`
+    Named,
+    /// Decimal or hexadecimal reference, like `&#169;` or `&#xA9;`
+    Numeric,
+}
+
+/// A resolved character reference as found in the input stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityRef {
+    /// The reference as found in the source (ie: `&copy;`)
+    pub raw: String,
+    /// The characters the reference resolved to (ie: `©`)
+    pub decoded: String,
+    /// Number of characters the reference resolved to. This differs from the length of the
+    /// span, and can be more than one for some named references (ie: `&nvlt;`).
+    pub decoded_len: usize,
+    /// Location of the reference in the input stream
+    pub span: Span,
+    /// Named or numeric reference
+    pub kind: EntityKind,
+}
+
+/// Outcome of matching a character reference without consuming it (see
+/// `Tokenizer::try_match_entity()`). The lengths count the characters of the reference in the
+/// input, including the ampersand and the semicolon (if any).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntityMatch {
+    /// A named or numeric reference terminated by a semicolon (ie: `&copy;` or `&#169;`)
+    FullWithSemicolon { len: usize },
+    /// A reference without the semicolon: a legacy named reference (ie: `&copy`) or a numeric
+    /// reference. It is resolved, but with a missing-semicolon parse error.
+    LegacyNoSemicolon { len: usize },
+    /// Not a character reference: the ampersand is literal text
+    NoMatch,
+    /// A numeric reference without digits (ie: `&#x;`), or with a value that is a parse error
+    /// (null, outside the unicode range, a surrogate, a noncharacter or a control character)
+    NumericInvalid { len: usize },
+}
+
+/// Decodes a single character reference (like `&amp;`, `&#169;` or `&#xA9;`) outside of the
+/// tokenizer. Returns None when the string is not exactly one valid character reference.
+pub fn decode_entity(entity: &str) -> Option<String> {
+    if !entity.starts_with('&') {
+        return None;
+    }
+
+    let mut chars = CharIterator::new();
+    chars.read_from_str(entity, None);
+
+    let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+    let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger);
+
+    // Skip the ampersand, as the data state would have done
+    tokenizer.chars.read_char();
+    let result = tokenizer.consume_character_reference(None, false);
+
+    if !result.was_entity || tokenizer.chars.look_ahead(0) != Eof {
+        return None;
+    }
+
+    Some(result.text)
+}
+
+/// Decodes all character references in the given text, the same way as the data state does.
+/// The text is not expected to contain any tags, so a `<` is kept as is. Newlines are normalized
+/// like in the input stream.
+pub fn decode_entities_in_text(text: &str) -> String {
+    let mut chars = CharIterator::new();
+    chars.read_from_str(text, None);
+
+    let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+    let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger);
+
+    let mut decoded = String::with_capacity(text.len());
+    loop {
+        match tokenizer.chars.read_char() {
+            Ch('&') => {
+                let result = tokenizer.consume_character_reference(None, false);
+                decoded.push_str(&result.text);
+            }
+            Ch(c) => decoded.push(c),
+            Surrogate(_) => decoded.push(CHAR_REPLACEMENT),
+            Eof => break,
+        }
+    }
+
+    decoded
+}
+
+/// Adds a digit to the code of a numeric character reference. Returns None once the code lies
+/// outside the unicode range, so huge references (ie: `&#99999999999999999;`) stay cheap and
+/// cannot overflow.
+fn add_digit(code: Option<u32>, radix: u32, digit: u32) -> Option<u32> {
+    code.map(|value| value * radix + digit)
+        .filter(|value| *value <= 0x10FFFF)
+}
+
+/// The context in which a character reference is found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefContext {
+    /// Text content (data and RCDATA states). Flushed into the consume buffer.
+    Data,
+    /// Double quoted attribute value. Flushed into the current attribute value.
+    AttributeDouble,
+    /// Single quoted attribute value. Flushed into the current attribute value.
+    AttributeSingle,
+    /// Unquoted attribute value. Flushed into the current attribute value.
+    AttributeUnquoted,
+}
+
+impl RefContext {
+    /// Returns true when the reference is part of an attribute value
+    pub fn is_attribute(&self) -> bool {
+        *self != RefContext::Data
+    }
+
+    /// Returns the character that terminates the attribute value (if any)
+    pub fn delimiter(&self) -> Option<Bytes> {
+        match self {
+            RefContext::Data => None,
+            RefContext::AttributeDouble => Some(Ch('"')),
+            RefContext::AttributeSingle => Some(Ch('\'')),
+            RefContext::AttributeUnquoted => Some(Ch('>')),
+        }
+    }
+}
+
 impl Tokenizer<'_> {
-    /// Consumes a character reference and places this in the tokenizer consume buffer
+    /// Consumes a character reference like `consume_character_reference_in()`. The context is
+    /// derived from the attribute delimiter (if any) and whether we are in an attribute value.
+    pub fn consume_character_reference(
+        &mut self,
+        additional_allowed_char: Option<Bytes>,
+        as_attribute: bool,
+    ) -> CharacterReference {
+        let context = match (as_attribute, additional_allowed_char) {
+            (false, _) => RefContext::Data,
+            (true, Some(Ch('"'))) => RefContext::AttributeDouble,
+            (true, Some(Ch('\''))) => RefContext::AttributeSingle,
+            (true, _) => RefContext::AttributeUnquoted,
+        };
+
+        self.consume_character_reference_in(context)
+    }
+
+    /// Consumes a character reference and places this in the tokenizer consume buffer, or in the
+    /// current attribute value when found in an attribute.
     /// ref: 8.2.4.69 Tokenizing character references
     ///
+    /// When the character directly after the `&` is not alphanumeric or `#` (for instance the
+    /// quote delimiter of an attribute value), the `&` is flushed as-is and that character is
+    /// unread, so the calling attribute value state still sees (and terminates on) the delimiter.
+    ///
+    /// Returns what has been flushed, and whether it was a (semicolon terminated) reference.
+    ///
     /// @TODO: fix additional allowed char
-    pub fn consume_character_reference(
+    pub fn consume_character_reference_in(&mut self, context: RefContext) -> CharacterReference {
+        let as_attribute = context.is_attribute();
+        let additional_allowed_char = context.delimiter();
+
+        let start = if as_attribute {
+            self.current_attr_value.len()
+        } else {
+            self.consumed.len()
+        };
+        // Position of the ampersand, which has already been read
+        let start_position = self
+            .entity_refs
+            .is_some()
+            .then(|| self.chars.get_previous_position());
+
+        let Some((was_entity, had_semicolon)) =
+            self.consume_character_reference_inner(additional_allowed_char, as_attribute)
+        else {
+            self.awaiting_reference = true;
+            return CharacterReference {
+                need_more_input: true,
+                ..Default::default()
+            };
+        };
+
+        let text = if as_attribute {
+            self.current_attr_value[start..].to_string()
+        } else {
+            self.consumed[start..].to_string()
+        };
+
+        if let Some(start_position) = start_position.filter(|_| was_entity) {
+            self.record_entity_ref(start_position, &text);
+        }
+
+        CharacterReference {
+            text,
+            had_semicolon,
+            was_entity,
+            need_more_input: false,
+        }
+    }
+
+    /// Tells how the character reference at the current position would be matched, without
+    /// consuming anything or raising parse errors. Like `consume_character_reference()`, the
+    /// ampersand must have been read already. The reference is matched as found in text: in
+    /// attribute values, legacy references followed by `=` or an alphanumeric are not resolved.
+    pub fn try_match_entity(&self) -> EntityMatch {
+        match self.chars.look_ahead(0) {
+            Ch('#') => self.try_match_numeric_entity(),
+            Ch(c) if c.is_ascii_alphanumeric() => match self.find_entity() {
+                Some(found) if found.has_semicolon => {
+                    EntityMatch::FullWithSemicolon { len: found.len + 1 }
+                }
+                Some(found) => EntityMatch::LegacyNoSemicolon { len: found.len + 1 },
+                None => EntityMatch::NoMatch,
+            },
+            _ => EntityMatch::NoMatch,
+        }
+    }
+
+    /// Matches a numeric reference for `try_match_entity()`. The next character is the `#`.
+    fn try_match_numeric_entity(&self) -> EntityMatch {
+        let mut offset = 1;
+        let radix = match self.chars.look_ahead(offset) {
+            Ch('x' | 'X') => {
+                offset += 1;
+                16
+            }
+            _ => 10,
+        };
+
+        let digits_start = offset;
+        let mut code = Some(0);
+        while let Ch(c) = self.chars.look_ahead(offset) {
+            let Some(digit) = c.to_digit(radix) else {
+                break;
+            };
+            code = add_digit(code, radix, digit);
+            offset += 1;
+        }
+
+        // The ampersand is not part of the offsets, as it has been read already
+        if offset == digits_start {
+            return EntityMatch::NumericInvalid { len: offset + 1 };
+        }
+
+        let had_semicolon = self.chars.look_ahead(offset) == Ch(';');
+        let len = if had_semicolon {
+            offset + 2
+        } else {
+            offset + 1
+        };
+
+        match code {
+            Some(code)
+                if code != 0
+                    && code != 0x0D
+                    && !self.is_surrogate(code)
+                    && !self.is_noncharacter(code)
+                    && !self.is_control_char(code) =>
+            {
+                if had_semicolon {
+                    EntityMatch::FullWithSemicolon { len }
+                } else {
+                    EntityMatch::LegacyNoSemicolon { len }
+                }
+            }
+            _ => EntityMatch::NumericInvalid { len },
+        }
+    }
+
+    /// Runs the character reference state machine. Returns a tuple with a flag whether a
+    /// reference was resolved, and a flag whether that reference was terminated by a semicolon.
+    /// Returns None when streaming and more input is needed to decide on a named reference.
+    fn consume_character_reference_inner(
         &mut self,
         _additional_allowed_char: Option<Bytes>,
         as_attribute: bool,
-    ) {
+    ) -> Option<(bool, bool)> {
         let mut ccr_state = CcrState::CharacterReference;
         let mut char_ref_code: Option<u32> = Some(0);
+        let mut had_semicolon = false;
 
         loop {
             match ccr_state {
@@ -39,6 +326,14 @@ impl Tokenizer<'_> {
                     self.temporary_buffer.push('&');
 
                     let c = self.read_char();
+
+                    // Whitespace (tab, LF, FF, space) falls into the "anything else" branch
+                    // below. CR never reaches this point, as the input stream normalizes it to LF.
+                    debug_assert!(
+                        c != Ch(CHAR_CR),
+                        "CR should be normalized by the input stream"
+                    );
+
                     match c {
                         Ch(ch) if ch.is_ascii_alphanumeric() => {
                             self.chars.unread();
@@ -48,53 +343,63 @@ impl Tokenizer<'_> {
                             self.temporary_buffer.push(c);
                             ccr_state = CcrState::NumericCharacterReference;
                         }
+                        Eof if self.streaming => {
+                            // Unread both the EOF and the ampersand
+                            self.temporary_buffer.clear();
+                            self.chars.unread();
+                            self.chars.unread();
+                            return None;
+                        }
                         _ => {
                             self.consume_temp_buffer(as_attribute);
 
                             self.chars.unread();
-                            return;
+                            return Some((false, false));
                         }
                     }
                 }
                 CcrState::NamedCharacterReference => {
-                    if let Some(entity) = self.find_entity() {
-                        self.chars.skip(entity.len());
+                    if self.streaming && self.is_incomplete_entity() {
+                        // Rewind to the ampersand, so it gets reconsumed when more input arrives
+                        self.temporary_buffer.clear();
+                        self.chars.unread();
+                        return None;
+                    }
+
+                    if let Some(found) = self.find_entity() {
+                        let mark = self.chars.tell();
+                        self.chars.skip(found.len);
                         let c = self.chars.look_ahead(0);
 
                         if as_attribute
-                            && !entity.ends_with(';')
+                            && !found.has_semicolon
                             && (c == Ch('=') || matches!(c, Ch(c) if c.is_ascii_alphanumeric()))
                         {
                             // for historical reasons, the codepoints should be flushed as
                             // is
-                            for c in entity.chars() {
-                                self.temporary_buffer.push(c);
-                            }
+                            let name = self.chars.slice(mark, mark + found.len);
+                            self.temporary_buffer.push_str(&name);
 
                             self.consume_temp_buffer(as_attribute);
-                            return;
+                            return Some((false, false));
                         }
 
-                        let entity_chars = *TOKEN_NAMED_CHARS.get(entity.as_str()).unwrap();
-
                         // Flush codepoints consumed as character reference
-                        for c in entity_chars.chars() {
-                            if as_attribute {
-                                self.current_attr_value.push(c);
-                            } else {
-                                self.consume(c);
-                            }
+                        if as_attribute {
+                            self.current_attr_value.push_str(found.replacement);
+                        } else {
+                            self.consumed.push_str(found.replacement);
                         }
                         self.temporary_buffer.clear();
 
-                        if !entity.ends_with(';') {
+                        if !found.has_semicolon {
                             // We need to return the position where we expected the ';'
                             self.chars.read_char(); // We can't use skip, as this might interfere with EOF stuff (fix it)
                             self.parse_error(ParserError::MissingSemicolonAfterCharacterReference);
                             self.chars.unread();
                         }
 
-                        return;
+                        return Some((true, found.has_semicolon));
                     }
 
                     self.consume_temp_buffer(as_attribute);
@@ -114,11 +419,14 @@ impl Tokenizer<'_> {
                         Ch(';') => {
                             self.parse_error(ParserError::UnknownNamedCharacterReference);
                             self.chars.unread();
-                            return;
+                            return Some((false, false));
                         }
                         _ => {
                             self.chars.unread();
-                            return;
+                            if self.error_on_unknown_entity {
+                                self.parse_error(ParserError::UnknownNamedCharacterReference);
+                            }
+                            return Some((false, false));
                         }
                     }
                 }
@@ -146,7 +454,7 @@ impl Tokenizer<'_> {
                         self.consume_temp_buffer(as_attribute);
 
                         self.chars.unread();
-                        return;
+                        return Some((false, false));
                     }
                 }
                 CcrState::DecimalCharacterReferenceStart => {
@@ -159,7 +467,7 @@ impl Tokenizer<'_> {
                         self.consume_temp_buffer(as_attribute);
 
                         self.chars.unread();
-                        return;
+                        return Some((false, false));
                     }
                 }
                 CcrState::HexadecimalCharacterReference => {
@@ -167,30 +475,16 @@ impl Tokenizer<'_> {
                     match c {
                         // Element::Eof => ccr_state = CcrState::NumericalCharacterReferenceEnd,
                         Ch(c @ '0'..='9') => {
-                            let i = c as u32 - 0x30;
-                            if let Some(value) = char_ref_code {
-                                char_ref_code = value
-                                    .checked_mul(16)
-                                    .and_then(|mul_result| mul_result.checked_add(i));
-                            }
+                            char_ref_code = add_digit(char_ref_code, 16, c as u32 - 0x30);
                         }
                         Ch(c @ 'A'..='F') => {
-                            let i = c as u32 - 0x37;
-                            if let Some(value) = char_ref_code {
-                                char_ref_code = value
-                                    .checked_mul(16)
-                                    .and_then(|mul_result| mul_result.checked_add(i));
-                            }
+                            char_ref_code = add_digit(char_ref_code, 16, c as u32 - 0x37);
                         }
                         Ch(c @ 'a'..='f') => {
-                            let i = c as u32 - 0x57;
-                            if let Some(value) = char_ref_code {
-                                char_ref_code = value
-                                    .checked_mul(16)
-                                    .and_then(|mul_result| mul_result.checked_add(i));
-                            }
+                            char_ref_code = add_digit(char_ref_code, 16, c as u32 - 0x57);
                         }
                         Ch(';') => {
+                            had_semicolon = true;
                             ccr_state = CcrState::NumericalCharacterReferenceEnd;
                         }
                         _ => {
@@ -205,14 +499,10 @@ impl Tokenizer<'_> {
                     match c {
                         // Element::Eof => ccr_state = CcrState::NumericalCharacterReferenceEndState,
                         Ch(c @ '0'..='9') => {
-                            let i = c as u32 - 0x30;
-                            if let Some(value) = char_ref_code {
-                                char_ref_code = value
-                                    .checked_mul(10)
-                                    .and_then(|mul_result| mul_result.checked_add(i));
-                            }
+                            char_ref_code = add_digit(char_ref_code, 10, c as u32 - 0x30);
                         }
                         Ch(';') => {
+                            had_semicolon = true;
                             ccr_state = CcrState::NumericalCharacterReferenceEnd;
                         }
                         _ => {
@@ -257,8 +547,8 @@ impl Tokenizer<'_> {
                         self.parse_error(ParserError::ControlCharacterReference);
                         self.chars.unread();
 
-                        if TOKEN_REPLACEMENTS.contains_key(&char_ref_code) {
-                            char_ref_code = *TOKEN_REPLACEMENTS.get(&char_ref_code).unwrap() as u32;
+                        if let Some(c) = c1_replacement(char_ref_code) {
+                            char_ref_code = c as u32;
                         }
                     }
 
@@ -267,12 +557,38 @@ impl Tokenizer<'_> {
                     self.temporary_buffer.push(c);
                     self.consume_temp_buffer(as_attribute);
 
-                    return;
+                    return Some((true, had_semicolon));
                 }
             }
         }
     }
 
+    /// Stores a resolved reference that starts at the given position and ends at the current
+    /// position in the stream
+    fn record_entity_ref(&mut self, start: Position, decoded: &str) {
+        let end = self.chars.position;
+        let raw: String = (1..=end.offset - start.offset)
+            .rev()
+            .filter_map(|offset| self.chars.look_behind(offset))
+            .collect();
+
+        let kind = if raw.starts_with("&#") {
+            EntityKind::Numeric
+        } else {
+            EntityKind::Named
+        };
+
+        if let Some(entity_refs) = &mut self.entity_refs {
+            entity_refs.push(EntityRef {
+                raw,
+                decoded: decoded.to_string(),
+                decoded_len: decoded.chars().count(),
+                span: Span::new(start, end),
+                kind,
+            });
+        }
+    }
+
     fn consume_temp_buffer(&mut self, as_attribute: bool) {
         if as_attribute {
             self.current_attr_value.push_str(&self.temporary_buffer);
@@ -306,38 +622,134 @@ impl Tokenizer<'_> {
         (0x0001..=0x001F).contains(&num) || (0x007F..=0x009F).contains(&num)
     }
 
-    /// Finds the longest entity from the current position in the stream. Returns the entity
-    /// replacement OR None when no entity has been found.
-    fn find_entity(&mut self) -> Option<String> {
-        let s = self.chars.look_ahead_slice(*LONGEST_ENTITY_LENGTH);
-        let chars: Vec<char> = s.chars().collect();
+    /// Returns true when the rest of the stream is the start of a longer named entity, so the
+    /// reference can only be resolved once more input has arrived (ie: "cop" for "copy;"), or
+    /// once `finish()` has marked the end of the input.
+    fn is_incomplete_entity(&self) -> bool {
+        let mut node = 0;
+        let mut offset = 0;
 
-        for i in (0..=s.len()).rev() {
-            if let Some(slice) = chars.get(0..i) {
-                let entity: String = slice.iter().collect();
-                if TOKEN_NAMED_CHARS.contains_key(entity.as_str()) {
-                    return Some(entity);
+        loop {
+            match self.chars.look_ahead(offset) {
+                Eof => return !ENTITY_TRIE.nodes[node].children.is_empty(),
+                Ch(c) => match ENTITY_TRIE.child(node, c) {
+                    Some(child) => node = child,
+                    None => return false,
+                },
+                _ => return false,
+            }
+            offset += 1;
+        }
+    }
+
+    /// Finds the longest entity from the current position in the stream by walking the entity
+    /// trie, without consuming anything. The match length tells how much of the stream the
+    /// entity name spans, so the caller can skip it (or slice it) in one go. Entities without a
+    /// trailing semicolon are skipped when `require_entity_semicolon` is set.
+    fn find_entity(&self) -> Option<FoundEntity> {
+        let mut node = 0;
+        let mut offset = 0;
+        let mut found = None;
+
+        while let Ch(c) = self.chars.look_ahead(offset) {
+            let Some(child) = ENTITY_TRIE.child(node, c) else {
+                break;
+            };
+
+            node = child;
+            offset += 1;
+            if let Some(replacement) = ENTITY_TRIE.nodes[node].replacement {
+                let has_semicolon = c == ';';
+                if !self.require_entity_semicolon || has_semicolon {
+                    found = Some(FoundEntity {
+                        len: offset,
+                        replacement,
+                        has_semicolon,
+                    });
                 }
             }
         }
-        None
+
+        found
+    }
+}
+
+/// A named reference as found in the stream by `find_entity()`
+#[derive(Debug, PartialEq)]
+struct FoundEntity {
+    /// Number of characters of the entity name, including the semicolon (if any)
+    len: usize,
+    /// Characters the entity resolves to
+    replacement: &'static str,
+    /// True when the entity name ends with a semicolon
+    has_semicolon: bool,
+}
+
+/// A single node in the entity trie
+struct TrieNode {
+    /// Replacement when an entity ends at this node
+    replacement: Option<&'static str>,
+    /// Nodes that longer entities continue with, sorted by their character
+    children: Vec<(char, usize)>,
+}
+
+/// Prefix tree over all named character references, so the longest matching entity can be found
+/// in a single pass over the input stream without building intermediate strings.
+struct EntityTrie {
+    nodes: Vec<TrieNode>,
+}
+
+impl EntityTrie {
+    fn new() -> Self {
+        let mut trie = Self {
+            nodes: vec![TrieNode {
+                replacement: None,
+                children: vec![],
+            }],
+        };
+
+        for (name, replacement) in TOKEN_NAMED_CHARS.entries() {
+            let mut node = 0;
+            for c in name.chars() {
+                node = match trie.nodes[node].children.binary_search_by_key(&c, |e| e.0) {
+                    Ok(idx) => trie.nodes[node].children[idx].1,
+                    Err(idx) => {
+                        trie.nodes.push(TrieNode {
+                            replacement: None,
+                            children: vec![],
+                        });
+                        let child = trie.nodes.len() - 1;
+                        trie.nodes[node].children.insert(idx, (c, child));
+                        child
+                    }
+                };
+            }
+            trie.nodes[node].replacement = Some(replacement);
+        }
+
+        trie
+    }
+
+    /// Returns the node reached from the given node by the given character
+    fn child(&self, node: usize, c: char) -> Option<usize> {
+        let children = &self.nodes[node].children;
+        children
+            .binary_search_by_key(&c, |e| e.0)
+            .ok()
+            .map(|idx| children[idx].1)
     }
 }
 
 lazy_static! {
-    // Returns the longest entity in the TOKEN_NAMED_CHARS map (this could be a const actually)
-    static ref LONGEST_ENTITY_LENGTH: usize = {
-        TOKEN_NAMED_CHARS.keys().map(|key| key.len()).max().unwrap_or(0)
-    };
+    static ref ENTITY_TRIE: EntityTrie = EntityTrie::new();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::html5::tokenizer::ParserData;
-    use crate::{bytes::CharIterator, html5::error_logger::ErrorLogger};
-    use std::cell::RefCell;
-    use std::rc::Rc;
+    use crate::html5::tokenizer::tests::{tokenize, with_tokenizer};
+    use crate::html5::tokenizer::token::Token;
+    use crate::html5::tokenizer::{Options, ParserData};
 
     macro_rules! entity_tests {
         ($($name:ident : $value:expr)*) => {
@@ -346,19 +758,567 @@ mod tests {
                 fn $name() {
                     let (input, expected) = $value;
 
-                    let mut chars = CharIterator::new();
-                    chars.read_from_str(input, None);
+                    let (tokens, _) = tokenize(input, None);
+                    assert_eq!(expected, tokens[0].to_string());
+                }
+            )*
+        }
+    }
 
-                    let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
-                    let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger.clone());
+    macro_rules! reference_tests {
+        ($($name:ident : $value:expr)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (input, text, had_semicolon, was_entity) = $value;
+
+                    let result = with_tokenizer(input, None, |tokenizer| {
+                        // Skip the ampersand, as the data state would have done
+                        tokenizer.chars.read_char();
+                        tokenizer.consume_character_reference(None, false)
+                    });
 
-                    let token = tokenizer.next_token(ParserData::default()).unwrap();
-                    assert_eq!(expected, token.to_string());
+                    assert_eq!(
+                        CharacterReference {
+                            text: text.to_string(),
+                            had_semicolon,
+                            was_entity,
+                            need_more_input: false,
+                        },
+                        result
+                    );
                 }
             )*
         }
     }
 
+    reference_tests! {
+        reference_0: ("&copy;", "©", true, true)
+        reference_1: ("&copy ", "©", false, true)
+        reference_2: ("&#169;", "©", true, true)
+        reference_3: ("&#169 ", "©", false, true)
+        reference_4: ("&#xA9;", "©", true, true)
+        reference_5: ("& ", "&", false, false)
+        reference_6: ("&#;", "&#", false, false)
+        reference_7: ("&unknown;", "&unknown", false, false)
+
+        // Astral (non-BMP) characters
+        reference_8: ("&#x1F600;", "😀", true, true)
+        reference_9: ("&#128512;", "😀", true, true)
+
+        // Hexadecimal prefix and digits are case-insensitive
+        reference_16: ("&#XA9;", "©", true, true)
+        reference_17: ("&#xab;", "«", true, true)
+        reference_18: ("&#xAB;", "«", true, true)
+        reference_19: ("&#XaB;", "«", true, true)
+
+        // Whitespace directly after the ampersand is never part of a reference
+        reference_10: ("&\tcopy;", "&", false, false)
+        reference_11: ("&\ncopy;", "&", false, false)
+        reference_12: ("&\u{c}copy;", "&", false, false)
+        reference_13: ("& copy;", "&", false, false)
+        reference_14: ("&\rcopy;", "&", false, false)
+        reference_15: ("&\r\ncopy;", "&", false, false)
+
+        // Numeric references without digits are kept literally, up to the end of the input
+        reference_20: ("&#x", "&#x", false, false)
+        reference_21: ("&#X", "&#X", false, false)
+        reference_22: ("&#", "&#", false, false)
+        reference_23: ("&#xg;", "&#x", false, false)
+    }
+
+    #[test]
+    fn astral_reference_is_single_char() {
+        for input in ["&#x1F600;a", "&#128512;a", "😀a"] {
+            let (token, errors, offset) = with_tokenizer(input, None, |tokenizer| {
+                let token = tokenizer.next_token(ParserData::default()).unwrap();
+                let errors = tokenizer.get_error_logger().get_errors();
+                (token, errors, tokenizer.chars.position.offset)
+            });
+            let Token::Text(value) = token else {
+                panic!("expected text token");
+            };
+            assert_eq!(value, "😀a");
+            assert_eq!(value.chars().count(), 2);
+            assert_eq!(value.len(), 5);
+            assert!(errors.is_empty());
+
+            // The position is counted in characters, not in bytes
+            assert_eq!(offset, input.chars().count());
+        }
+    }
+
+    #[test]
+    fn vertical_tab_reference() {
+        for input in ["&#xB;", "&#11;"] {
+            // A vertical tab is a control character that is emitted as-is, with a parse error
+            let (tokens, errors) = tokenize(input, None);
+            assert_eq!(vec![Token::Text("\u{000B}".into())], tokens);
+
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].message, "control-character-reference");
+        }
+    }
+
+    #[test]
+    fn null_character_reference() {
+        for (input, errors) in [
+            ("&#0;", vec!["null-character-reference"]),
+            ("&#x0;", vec!["null-character-reference"]),
+            ("&#X0000;", vec!["null-character-reference"]),
+            ("&#000;", vec!["null-character-reference"]),
+            (
+                "&#0",
+                vec![
+                    "missing-semicolon-after-character-reference",
+                    "null-character-reference",
+                ],
+            ),
+        ] {
+            let (tokens, logged) = tokenize(input, None);
+            assert_eq!(
+                vec![Token::Text(CHAR_REPLACEMENT.into())],
+                tokens,
+                "for {input:?}"
+            );
+
+            let logged: Vec<String> = logged.into_iter().map(|e| e.message).collect();
+            assert_eq!(logged, errors, "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn numeric_reference_without_digits_at_eof() {
+        for input in ["a&#x", "a&#X", "a&#"] {
+            let (tokens, errors) = tokenize(input, None);
+            assert_eq!(vec![Token::Text(input.into())], tokens);
+
+            assert_eq!(errors.len(), 1, "for {input:?}");
+            assert_eq!(
+                errors[0].message,
+                "absence-of-digits-in-numeric-character-reference"
+            );
+        }
+    }
+
+    #[test]
+    fn c1_replacements() {
+        // The table from the numeric character reference end state in the spec
+        let table = [
+            (0x80, '\u{20AC}'),
+            (0x82, '\u{201A}'),
+            (0x83, '\u{0192}'),
+            (0x84, '\u{201E}'),
+            (0x85, '\u{2026}'),
+            (0x86, '\u{2020}'),
+            (0x87, '\u{2021}'),
+            (0x88, '\u{02C6}'),
+            (0x89, '\u{2030}'),
+            (0x8A, '\u{0160}'),
+            (0x8B, '\u{2039}'),
+            (0x8C, '\u{0152}'),
+            (0x8E, '\u{017D}'),
+            (0x91, '\u{2018}'),
+            (0x92, '\u{2019}'),
+            (0x93, '\u{201C}'),
+            (0x94, '\u{201D}'),
+            (0x95, '\u{2022}'),
+            (0x96, '\u{2013}'),
+            (0x97, '\u{2014}'),
+            (0x98, '\u{02DC}'),
+            (0x99, '\u{2122}'),
+            (0x9A, '\u{0161}'),
+            (0x9B, '\u{203A}'),
+            (0x9C, '\u{0153}'),
+            (0x9E, '\u{017E}'),
+            (0x9F, '\u{0178}'),
+        ];
+
+        for code in 0x80..=0x9F {
+            let expected = table
+                .iter()
+                .find(|(c, _)| *c == code)
+                .map(|(_, replacement)| *replacement);
+            assert_eq!(c1_replacement(code), expected, "for {code:#X}");
+
+            // Code points without a replacement are emitted as-is
+            let input = format!("&#x{code:X};");
+            let (tokens, _) = tokenize(&input, None);
+            let c = expected.unwrap_or(char::from_u32(code).unwrap());
+            assert_eq!(vec![Token::Text(c.into())], tokens, "for {input:?}");
+        }
+
+        assert_eq!(c1_replacement(0x7F), None);
+        assert_eq!(c1_replacement(0xA0), None);
+    }
+
+    #[test]
+    fn try_match_entity() {
+        for (input, expected) in [
+            ("&copy;", EntityMatch::FullWithSemicolon { len: 6 }),
+            ("&notin; b", EntityMatch::FullWithSemicolon { len: 7 }),
+            ("&#169;", EntityMatch::FullWithSemicolon { len: 6 }),
+            ("&#xA9;x", EntityMatch::FullWithSemicolon { len: 6 }),
+            ("&copy", EntityMatch::LegacyNoSemicolon { len: 5 }),
+            ("&notit;", EntityMatch::LegacyNoSemicolon { len: 4 }),
+            ("&#169 ", EntityMatch::LegacyNoSemicolon { len: 5 }),
+            ("&", EntityMatch::NoMatch),
+            ("& copy;", EntityMatch::NoMatch),
+            ("&unknown;", EntityMatch::NoMatch),
+            ("&#;", EntityMatch::NumericInvalid { len: 2 }),
+            ("&#xg;", EntityMatch::NumericInvalid { len: 3 }),
+            ("&#0;", EntityMatch::NumericInvalid { len: 4 }),
+            ("&#x110000;", EntityMatch::NumericInvalid { len: 10 }),
+            (
+                "&#99999999999999999999",
+                EntityMatch::NumericInvalid { len: 22 },
+            ),
+            ("&#xD800;", EntityMatch::NumericInvalid { len: 8 }),
+            ("&#xFFFF;", EntityMatch::NumericInvalid { len: 8 }),
+            ("&#x80;", EntityMatch::NumericInvalid { len: 6 }),
+        ] {
+            with_tokenizer(input, None, |tokenizer| {
+                // Skip the ampersand, as the data state would have done
+                tokenizer.chars.read_char();
+                assert_eq!(tokenizer.try_match_entity(), expected, "for {input:?}");
+
+                // Nothing has been consumed
+                assert_eq!(tokenizer.chars.position.offset, 1);
+                assert!(tokenizer.get_error_logger().get_errors().is_empty());
+            });
+        }
+    }
+
+    #[test]
+    fn reference_contexts() {
+        for (context, input, expected, next) in [
+            (RefContext::Data, "&not=x", "¬", Ch('=')),
+            (RefContext::Data, "&\"", "&", Ch('"')),
+            // Legacy references followed by '=' or an alphanumeric are kept as-is in attributes
+            (RefContext::AttributeDouble, "&not=x", "&not", Ch('=')),
+            (RefContext::AttributeDouble, "&\"", "&", Ch('"')),
+            (RefContext::AttributeSingle, "&notx'", "&not", Ch('x')),
+            (RefContext::AttributeSingle, "&'", "&", Ch('\'')),
+            (RefContext::AttributeUnquoted, "&amp;>", "&", Ch('>')),
+            (RefContext::AttributeUnquoted, "&>", "&", Ch('>')),
+        ] {
+            with_tokenizer(input, None, |tokenizer| {
+                // Skip the ampersand, as the calling state would have done
+                tokenizer.chars.read_char();
+                let result = tokenizer.consume_character_reference_in(context);
+                assert_eq!(result.text, expected, "for {input:?} in {context:?}");
+
+                let flushed = if context.is_attribute() {
+                    &tokenizer.current_attr_value
+                } else {
+                    &tokenizer.consumed
+                };
+                assert_eq!(flushed, expected, "for {input:?} in {context:?}");
+                assert_eq!(tokenizer.chars.look_ahead(0), next);
+            });
+        }
+
+        // The old signature maps onto the same contexts
+        let result = with_tokenizer("&not=", None, |tokenizer| {
+            tokenizer.chars.read_char();
+            tokenizer.consume_character_reference(Some(Ch('"')), true)
+        });
+        assert_eq!(result.text, "&not");
+    }
+
+    #[test]
+    fn whitespace_references() {
+        for (input, expected) in [
+            ("&#x09;", "\t"),
+            ("&#x0A;", "\n"),
+            ("&#x0C;", "\u{000C}"),
+            ("&#x20;", " "),
+            ("&#9;&#10;&#12;&#32;", "\t\n\u{000C} "),
+            // The same characters directly after a bare ampersand are plain text
+            ("&\t&\n&\u{000C}& ", "&\t&\n&\u{000C}& "),
+        ] {
+            // Whitespace is not part of the reserved control character range, so no errors
+            let (tokens, errors) = tokenize(input, None);
+            assert_eq!(vec![Token::Text(expected.into())], tokens, "for {input:?}");
+            assert!(errors.is_empty(), "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn ambiguous_ampersand_errors() {
+        for (input, expected, errors) in [
+            ("a & b", "a & b", vec![]),
+            ("a &xyz b", "a &xyz b", vec![]),
+            (
+                "a &xyz; b",
+                "a &xyz; b",
+                vec!["unknown-named-character-reference"],
+            ),
+            (
+                "a &# b",
+                "a &# b",
+                vec!["absence-of-digits-in-numeric-character-reference"],
+            ),
+            (
+                "a &#x b",
+                "a &#x b",
+                vec!["absence-of-digits-in-numeric-character-reference"],
+            ),
+            // Both the ampersand and the number sign are kept, the semicolon is regular text
+            (
+                "&#;",
+                "&#;",
+                vec!["absence-of-digits-in-numeric-character-reference"],
+            ),
+        ] {
+            let (tokens, logged) = tokenize(input, None);
+            assert_eq!(vec![Token::Text(expected.into())], tokens);
+
+            let logged: Vec<String> = logged.into_iter().map(|e| e.message).collect();
+            assert_eq!(logged, errors, "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn ambiguous_ampersand_in_attributes() {
+        let input = r#"<p title="x=&foo" alt="&foo;" href="?a&copy=1" data-x="&copyx" lang=&not;>"#;
+
+        let (tokens, errors) = tokenize(input, None);
+        assert!(tokens[0].is_any_start_tag(), "expected start tag");
+        let attributes = tokens[0].attr_map();
+
+        // Unknown names and legacy references followed by '=' or alphanumerics stay literal
+        assert_eq!(attributes.get("title").unwrap(), "x=&foo");
+        assert_eq!(attributes.get("alt").unwrap(), "&foo;");
+        assert_eq!(attributes.get("href").unwrap(), "?a&copy=1");
+        assert_eq!(attributes.get("data-x").unwrap(), "&copyx");
+        assert_eq!(attributes.get("lang").unwrap(), "¬");
+
+        // Only the unknown reference terminated by a semicolon is an error
+        let logged: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+        assert_eq!(logged, vec!["unknown-named-character-reference"]);
+    }
+
+    #[test]
+    fn error_on_unknown_entity() {
+        for (input, enabled, errors) in [
+            // Terminated by a semicolon, the spec always requires an error
+            (
+                "&unknown;",
+                false,
+                vec!["unknown-named-character-reference"],
+            ),
+            ("&unknown;", true, vec!["unknown-named-character-reference"]),
+            ("&unknown b", false, vec![]),
+            (
+                "&unknown b",
+                true,
+                vec!["unknown-named-character-reference"],
+            ),
+            ("a & b", true, vec![]),
+        ] {
+            let opts = Options {
+                error_on_unknown_entity: enabled,
+                ..Default::default()
+            };
+
+            // The literal text is passed through either way
+            let (tokens, logged) = tokenize(input, Some(opts));
+            assert_eq!(vec![Token::Text(input.into())], tokens);
+
+            let logged: Vec<String> = logged.into_iter().map(|e| e.message).collect();
+            assert_eq!(logged, errors, "for {input:?} (enabled: {enabled})");
+        }
+    }
+
+    #[test]
+    fn entity_names_are_case_sensitive() {
+        for (input, expected, errors) in [
+            ("&copy;", "©", vec![]),
+            // The official table defines the uppercase variants of a few legacy entities
+            ("&COPY;", "©", vec![]),
+            (
+                "&COPY",
+                "©",
+                vec!["missing-semicolon-after-character-reference"],
+            ),
+            ("&AMP;", "&", vec![]),
+            // Other casings are not references at all
+            (
+                "&Copy;",
+                "&Copy;",
+                vec!["unknown-named-character-reference"],
+            ),
+            (
+                "&cOPY;",
+                "&cOPY;",
+                vec!["unknown-named-character-reference"],
+            ),
+            ("&Amp;", "&Amp;", vec!["unknown-named-character-reference"]),
+            // Numeric references are case-insensitive in the hexadecimal prefix and digits
+            ("&#xa9;&#XA9;&#Xa9;", "©©©", vec![]),
+            (
+                "&#x80;&#X80;",
+                "€€",
+                vec!["control-character-reference", "control-character-reference"],
+            ),
+        ] {
+            let (tokens, logged) = tokenize(input, None);
+            assert_eq!(vec![Token::Text(expected.into())], tokens, "for {input:?}");
+
+            let logged: Vec<String> = logged.into_iter().map(|e| e.message).collect();
+            assert_eq!(logged, errors, "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn require_entity_semicolon() {
+        for (input, enabled, expected) in [
+            ("&copy", false, "©"),
+            ("&copy", true, "&copy"),
+            ("&copy;", true, "©"),
+            ("&copy x", true, "&copy x"),
+            ("&notin", true, "&notin"),
+            ("&notin;", true, "∉"),
+            ("&#169", true, "©"),
+            (r#"<a title="&copy">"#, true, r#"<a title="&copy">"#),
+        ] {
+            let opts = Options {
+                require_entity_semicolon: enabled,
+                ..Default::default()
+            };
+            let (tokens, _) = tokenize(input, Some(opts));
+            assert_eq!(
+                tokens[0].to_string(),
+                expected,
+                "for {input:?} (enabled: {enabled})"
+            );
+        }
+    }
+
+    #[test]
+    fn collect_entities() {
+        let refs = with_tokenizer(
+            "a&copy;b\n<p title='&#169;'>&not c&unknown;",
+            None,
+            |tokenizer| tokenizer.collect_entities().unwrap(),
+        );
+        let found: Vec<_> = refs
+            .iter()
+            .map(|r| {
+                (
+                    r.raw.as_str(),
+                    r.decoded.as_str(),
+                    r.kind,
+                    (r.span.start.line, r.span.start.col),
+                    (r.span.end.line, r.span.end.col),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("&copy;", "©", EntityKind::Named, (1, 2), (1, 8)),
+                ("&#169;", "©", EntityKind::Numeric, (2, 11), (2, 17)),
+                ("&not", "¬", EntityKind::Named, (2, 19), (2, 23)),
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_char_entity_span() {
+        let refs = with_tokenizer("a&nvlt;b", None, |tokenizer| {
+            tokenizer.collect_entities().unwrap()
+        });
+        assert_eq!(refs.len(), 1);
+
+        // The six source characters resolve into two codepoints
+        let entity = &refs[0];
+        assert_eq!(entity.decoded, "\u{003C}\u{20D2}");
+        assert_eq!(entity.decoded_len, 2);
+        assert_eq!(entity.span.start.offset, 1);
+        assert_eq!(entity.span.end.offset, 7);
+        assert_eq!(entity.raw.chars().count(), 6);
+
+        // The emitted text token spans eight source characters, but holds only four
+        let text = with_tokenizer("a&nvlt;b", None, |tokenizer| {
+            tokenizer.next_spanned_token(ParserData::default()).unwrap()
+        });
+        assert_eq!(text.token, Token::Text("a\u{003C}\u{20D2}b".into()));
+        assert_eq!(text.span.start.offset, 0);
+        assert_eq!(text.span.end.offset, 8);
+        assert_eq!(text.decoded_len, 4);
+    }
+
+    #[test]
+    fn decode_single_entity() {
+        assert_eq!(decode_entity("&#169;"), Some("©".into()));
+        assert_eq!(decode_entity("&#xA9;"), Some("©".into()));
+        assert_eq!(decode_entity("&copy;"), Some("©".into()));
+        assert_eq!(decode_entity("&amp;"), Some("&".into()));
+        assert_eq!(decode_entity("&nope;"), None);
+        assert_eq!(decode_entity("&copy;x"), None);
+        assert_eq!(decode_entity("copy;"), None);
+        assert_eq!(decode_entity(""), None);
+    }
+
+    #[test]
+    fn decode_entities_in_plain_text() {
+        assert_eq!(
+            decode_entities_in_text("1 &lt; 2 &amp;&amp; 3 &gt; 0"),
+            "1 < 2 && 3 > 0"
+        );
+        assert_eq!(decode_entities_in_text("&copy 2024 &#x263A;"), "© 2024 ☺");
+        // Tags and things that are not references are left alone
+        assert_eq!(
+            decode_entities_in_text("<b>&</b> &nope; &"),
+            "<b>&</b> &nope; &"
+        );
+        assert_eq!(decode_entities_in_text(""), "");
+    }
+
+    #[test]
+    fn trie_finds_longest_entity() {
+        // Longest prefix lookup as done before the trie, by trying every prefix in the table
+        let longest_prefix = |s: &str| {
+            (0..=s.len())
+                .rev()
+                .filter_map(|i| s.get(0..i))
+                .find_map(|prefix| TOKEN_NAMED_CHARS.get_entry(prefix))
+                .map(|(name, replacement)| (*name, *replacement))
+        };
+
+        let mut inputs = vec![
+            "copyThing;",
+            "notit;",
+            "notin;",
+            "fo",
+            "copya ",
+            "unknownchar;",
+            "CounterClockwiseContourIntegral;x",
+            "ampamp;",
+            "",
+        ];
+        inputs.extend(TOKEN_NAMED_CHARS.keys());
+
+        for input in inputs {
+            let found = with_tokenizer(input, None, |tokenizer| tokenizer.find_entity());
+            let expected = longest_prefix(input).map(|(name, replacement)| FoundEntity {
+                len: name.len(),
+                replacement,
+                has_semicolon: name.ends_with(';'),
+            });
+            assert_eq!(found, expected, "for {input:?}");
+        }
+    }
+
+    #[test]
+    fn carriage_return_is_normalized() {
+        let (tokens, _) = tokenize("&\r&\r\n", None);
+        assert_eq!("&\n&\n", tokens[0].to_string());
+    }
+
     entity_tests! {
         // Numbers
         entity_0: ("&#10;", "\n")
@@ -368,7 +1328,7 @@ mod tests {
         entity_4: ("&#xd888;", "�")         // replace with replacement char
         entity_5: ("&#xbeef;", "뻯")
         entity_6: ("&#x10;", "\u{10}")
-        entity_7: ("&#;", "&#;")
+        entity_7: ("&#;", "&#;")            // "&#" is flushed as-is (absence-of-digits error)
         entity_8: ("&;", "&;")
         entity_9: ("&", "&")
         entity_10: ("&#x1;", "\u{1}")                // reserved codepoint
@@ -466,5 +1426,24 @@ mod tests {
         entity_259: ("&#11;", "\u{b}")
         entity_260: ("&#12;", "\u{c}")
         entity_261: ("&#13;", "\u{d}")
+
+        // Consecutive references without separators
+        entity_262: ("&amp;&amp;", "&&")
+        entity_263: ("&lt;&gt;", "<>")
+        entity_264: ("&amp;amp;", "&amp;")
+        entity_265: ("&#60;&#x3E;&lt;", "<><")
+        entity_266: ("&copy&copy;", "©©")
+        entity_267: ("&&amp;", "&&")
+        // The ampersand of an expanded reference does not start a new reference
+        entity_273: ("&amp;#169;", "&#169;")
+        entity_274: ("&amp;#x41;", "&#x41;")
+        entity_275: ("&lt;copy;", "<copy;")
+
+        // Values far outside of the unicode range
+        entity_268: ("&#99999999999999999;", "�")
+        entity_269: ("&#x99999999999999999;", "�")
+        entity_270: ("&#x110000;", "�")
+        entity_271: ("&#x10FFFF;", "\u{10FFFF}")
+        entity_272: ("&#00000000000000000065;", "A")
     }
 }
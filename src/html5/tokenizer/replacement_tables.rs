@@ -1,78 +1,44 @@
 use phf::phf_map;
 
-/// These are token replacements as defined by
-/// https://dev.w3.org/html5/spec-LC/tokenization.html#consume-a-character-reference
-/// If a character (#0x80; for instance) is found, it must be replaced by the given character
-pub static TOKEN_REPLACEMENTS: phf::Map<u32, char> = phf::phf_map! {
-    // REPLACEMENT CHARACTER
-    0x00_u32 => '\u{FFFD}',
-    // CARRIAGE RETURN (CR)
-    0x0d_u32 => '\u{000D}',
-    // EURO SIGN (€)
-    0x80_u32 => '\u{20AC}',
-    // <control>
-    0x81_u32 => '\u{0081}',
-    // SINGLE LOW-9 QUOTATION MARK (‚)
-    0x82_u32 => '\u{201A}',
-    // LATIN SMALL LETTER F WITH HOOK (ƒ)
-    0x83_u32 => '\u{0192}',
-    // DOUBLE LOW-9 QUOTATION MARK („)
-    0x84_u32 => '\u{201E}',
-    // HORIZONTAL ELLIPSIS (…)
-    0x85_u32 => '\u{2026}',
-    // DAGGER (†)
-    0x86_u32 => '\u{2020}',
-    // DOUBLE DAGGER (‡)
-    0x87_u32 => '\u{2021}',
-    // MODIFIER LETTER CIRCUMFLEX ACCENT (ˆ)
-    0x88_u32 => '\u{02C6}',
-    // PER MILLE SIGN (‰)
-    0x89_u32 => '\u{2030}',
-    // LATIN CAPITAL LETTER S WITH CARON (Š)
-    0x8A_u32 => '\u{0160}',
-    // SINGLE LEFT-POINTING ANGLE QUOTATION MARK (‹)
-    0x8B_u32 => '\u{2039}',
-    // LATIN CAPITAL LIGATURE OE (Œ)
-    0x8C_u32 => '\u{0152}',
-    // <control>
-    0x8D_u32 => '\u{008D}',
-    // LATIN CAPITAL LETTER Z WITH CARON (Ž)
-    0x8E_u32 => '\u{017D}',
-    // <control>
-    0x8F_u32 => '\u{008F}',
-    // <control>
-    0x90_u32 => '\u{0090}',
-    // LEFT SINGLE QUOTATION MARK (‘)
-    0x91_u32 => '\u{2018}',
-    // RIGHT SINGLE QUOTATION MARK (’)
-    0x92_u32 => '\u{2019}',
-    // LEFT DOUBLE QUOTATION MARK (“)
-    0x93_u32 => '\u{201C}',
-    // 	RIGHT DOUBLE QUOTATION MARK (”)
-    0x94_u32 => '\u{201D}',
-    // BULLET (•)
-    0x95_u32 => '\u{2022}',
-    // EN DASH (–)
-    0x96_u32 => '\u{2013}',
-    // EM DASH (—)
-    0x97_u32 => '\u{2014}',
-    // SMALL TILDE (˜)
-    0x98_u32 => '\u{02DC}',
-    // TRADE MARK SIGN (™)
-    0x99_u32 => '\u{2122}',
-    // LATIN SMALL LETTER S WITH CARON (š)
-    0x9A_u32 => '\u{0161}',
-    // SINGLE RIGHT-POINTING ANGLE QUOTATION MARK (›)
-    0x9B_u32 => '\u{203A}',
-    // LATIN SMALL LIGATURE OE (œ)
-    0x9C_u32 => '\u{0153}',
-    // <control>
-    0x9D_u32 => '\u{009D}',
-    // LATIN SMALL LETTER Z WITH CARON (ž)
-    0x9E_u32 => '\u{017E}',
-    // LATIN CAPITAL LETTER Y WITH DIAERESIS (Ÿ)
-    0x9F_u32 => '\u{0178}',
-};
+/// Returns the replacement of a numeric character reference in the C1 control range, as
+/// defined by the table in
+/// https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+/// These are the windows-1252 characters of the same bytes (ie: `&#x80;` is the euro sign).
+/// Code points without an entry (like 0x81) are not replaced.
+pub fn c1_replacement(code: u32) -> Option<char> {
+    let c = match code {
+        0x80 => '\u{20AC}', // EURO SIGN (€)
+        0x82 => '\u{201A}', // SINGLE LOW-9 QUOTATION MARK (‚)
+        0x83 => '\u{0192}', // LATIN SMALL LETTER F WITH HOOK (ƒ)
+        0x84 => '\u{201E}', // DOUBLE LOW-9 QUOTATION MARK („)
+        0x85 => '\u{2026}', // HORIZONTAL ELLIPSIS (…)
+        0x86 => '\u{2020}', // DAGGER (†)
+        0x87 => '\u{2021}', // DOUBLE DAGGER (‡)
+        0x88 => '\u{02C6}', // MODIFIER LETTER CIRCUMFLEX ACCENT (ˆ)
+        0x89 => '\u{2030}', // PER MILLE SIGN (‰)
+        0x8A => '\u{0160}', // LATIN CAPITAL LETTER S WITH CARON (Š)
+        0x8B => '\u{2039}', // SINGLE LEFT-POINTING ANGLE QUOTATION MARK (‹)
+        0x8C => '\u{0152}', // LATIN CAPITAL LIGATURE OE (Œ)
+        0x8E => '\u{017D}', // LATIN CAPITAL LETTER Z WITH CARON (Ž)
+        0x91 => '\u{2018}', // LEFT SINGLE QUOTATION MARK (‘)
+        0x92 => '\u{2019}', // RIGHT SINGLE QUOTATION MARK (’)
+        0x93 => '\u{201C}', // LEFT DOUBLE QUOTATION MARK (“)
+        0x94 => '\u{201D}', // RIGHT DOUBLE QUOTATION MARK (”)
+        0x95 => '\u{2022}', // BULLET (•)
+        0x96 => '\u{2013}', // EN DASH (–)
+        0x97 => '\u{2014}', // EM DASH (—)
+        0x98 => '\u{02DC}', // SMALL TILDE (˜)
+        0x99 => '\u{2122}', // TRADE MARK SIGN (™)
+        0x9A => '\u{0161}', // LATIN SMALL LETTER S WITH CARON (š)
+        0x9B => '\u{203A}', // SINGLE RIGHT-POINTING ANGLE QUOTATION MARK (›)
+        0x9C => '\u{0153}', // LATIN SMALL LIGATURE OE (œ)
+        0x9E => '\u{017E}', // LATIN SMALL LETTER Z WITH CARON (ž)
+        0x9F => '\u{0178}', // LATIN CAPITAL LETTER Y WITH DIAERESIS (Ÿ)
+        _ => return None,
+    };
+
+    Some(c)
+}
 
 /// Converted from golang.org/x/net/html/entity.go
 /// Taken from https://dev.w3.org/html5/spec-LC/named-character-references.html#named-character-references
@@ -85,9 +85,16 @@ pub enum State {
     /// 8.2.4.49 Comment end dash state
     CommentEndDash,
 
+    /// Comment less-than sign state
     CommentLessThanSign,
+
+    /// Comment less-than sign bang state
     CommentLessThanSignBang,
+
+    /// Comment less-than sign bang dash state
     CommentLessThanSignBangDash,
+
+    /// Comment less-than sign bang dash dash state
     CommentLessThanSignBangDashDash,
 
     /// 8.2.4.46 Comment start state
@@ -125,6 +132,9 @@ pub enum State {
     /// 8.2.4.7 PLAINTEXT state
     PLAINTEXT,
 
+    /// Processing instruction state (only used in the XmlLike parse mode)
+    ProcessingInstruction,
+
     /// 8.2.4.5 RAWTEXT state
     RAWTEXT,
 
@@ -1,14 +1,44 @@
+use crate::bytes::Span;
 use crate::html5::tokenizer::CHAR_NUL;
 use std::collections::HashMap;
+use std::ops::Range;
 
+/// A single attribute as found in the source
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Attribute {
     pub name: String,
+    /// Value with all character references decoded (ie: `javascript:`)
     pub value: String,
+    /// Value exactly as found in the source, without the quotes (ie: `&#x6a;avascript:`)
+    pub raw_value: String,
+}
+
+impl Attribute {
+    /// Creates an attribute that has not been found in the source, so its raw value is the same
+    /// as its value
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            raw_value: value.to_owned(),
+        }
+    }
+}
+
+/// How a comment has been closed in the source
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CommentClosing {
+    /// Closed with `-->`, or not closed at all when the end of the input was reached
+    #[default]
+    Normal,
+    /// Closed with a single `>`, as in `<!-->` or a bogus comment like `<?x>`
+    Abrupt,
+    /// Closed with `--!>`
+    Bang,
 }
 
 /// The different token structures that can be emitted by the tokenizer
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Token {
     DocType {
         name: Option<String>,
@@ -19,13 +49,24 @@ pub enum Token {
     StartTag {
         name: String,
         is_self_closing: bool,
-        attributes: HashMap<String, String>,
+        /// All attributes in source order. Duplicates are only kept when the tokenizer runs with
+        /// the `preserve_duplicate_attributes` option. See `attr_map()` for a map of them.
+        attributes: Vec<Attribute>,
     },
     EndTag {
         name: String,
         is_self_closing: bool,
     },
-    Comment(String),
+    Comment {
+        value: String,
+        /// How the comment has been closed, so serializers can reproduce the source
+        closing: CommentClosing,
+    },
+    /// Processing instruction (`<?target data?>`). Only emitted in the XmlLike parse mode.
+    ProcessingInstruction {
+        target: String,
+        data: String,
+    },
     Text(String),
     Eof,
 }
@@ -102,6 +143,34 @@ impl Token {
         }
     }
 
+    /// Returns the attributes of a start tag as a map, for consumers that do not care about their
+    /// order. Duplicates are dropped: the first attribute with a name wins. Other tokens return an
+    /// empty map.
+    pub fn attr_map(&self) -> HashMap<String, String> {
+        let Token::StartTag { attributes, .. } = self else {
+            return HashMap::new();
+        };
+
+        let mut map = HashMap::with_capacity(attributes.len());
+        for attr in attributes {
+            map.entry(attr.name.clone())
+                .or_insert_with(|| attr.value.clone());
+        }
+        map
+    }
+
+    /// Returns the value of the first attribute of a start tag with the given name
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        let Token::StartTag { attributes, .. } = self else {
+            return None;
+        };
+
+        attributes
+            .iter()
+            .find(|attr| attr.name == name)
+            .map(|attr| attr.value.as_str())
+    }
+
     pub(crate) fn is_any_start_tag(&self) -> bool {
         matches!(self, Token::StartTag { .. })
     }
@@ -111,6 +180,77 @@ impl Token {
     }
 }
 
+/// A token together with the part of the input it has been tokenized from, as returned by
+/// `Tokenizer::next_spanned_token()`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    /// Location of the token in the input stream
+    pub span: Span,
+    /// Number of characters of a text token after character references have been resolved. This
+    /// differs from the length of the span when a reference resolved into fewer or more
+    /// characters than it spans (ie: `&nvlt;` resolves into two). Zero for all other tokens.
+    pub decoded_len: usize,
+    /// Location of the token in the original input in bytes. Only known when the input stream
+    /// tracks byte offsets (see `CharIterator::set_track_byte_offsets()`).
+    pub byte_range: Option<Range<usize>>,
+}
+
+impl SpannedToken {
+    /// Returns the part of the given input the token has been tokenized from, so rewriters can
+    /// copy tokens they do not modify verbatim instead of serializing them again. The input must
+    /// be the original input of the tokenizer. Returns None when its byte offsets are unknown.
+    pub fn raw_source<'a>(&self, input: &'a str) -> Option<&'a str> {
+        input.get(self.byte_range.clone()?)
+    }
+}
+
+/// Iterator adapter that merges consecutive text tokens into a single text token
+pub struct CoalesceCharacters<I: Iterator<Item = Token>> {
+    /// Underlying token iterator
+    inner: I,
+    /// Token that was read ahead of the current text token and must be returned next
+    pending: Option<Token>,
+}
+
+impl<I: Iterator<Item = Token>> Iterator for CoalesceCharacters<I> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.pending.take().or_else(|| self.inner.next())?;
+
+        let Token::Text(mut value) = token else {
+            return Some(token);
+        };
+
+        // Keep appending text until we find a non-text token, which we store for the next call
+        for next in self.inner.by_ref() {
+            match next {
+                Token::Text(more) => value.push_str(&more),
+                _ => {
+                    self.pending = Some(next);
+                    break;
+                }
+            }
+        }
+
+        Some(Token::Text(value))
+    }
+}
+
+/// Extension trait that adds the coalesce_characters() adapter to any token iterator
+pub trait CoalesceCharactersExt: Iterator<Item = Token> + Sized {
+    /// Merges consecutive text tokens so downstream consumers see a single text token per run
+    fn coalesce_characters(self) -> CoalesceCharacters<Self> {
+        CoalesceCharacters {
+            inner: self,
+            pending: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Token>> CoalesceCharactersExt for I {}
+
 // Each token can be displayed as a string
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -131,16 +271,18 @@ impl std::fmt::Display for Token {
                 result.push_str(" />");
                 write!(f, "{result}")
             }
-            Token::Comment(value) => write!(f, "<!-- {value} -->"),
+            Token::Comment { value, .. } => write!(f, "<!-- {value} -->"),
+            Token::ProcessingInstruction { target, data } => write!(f, "<?{target} {data}?>"),
             Token::Text(value) => write!(f, "{value}"),
             Token::StartTag {
                 name,
                 is_self_closing,
                 attributes,
+                ..
             } => {
                 let mut result = format!("<{name}");
-                for (key, value) in attributes {
-                    result.push_str(&format!(r#" {key}="{value}""#));
+                for Attribute { name, value, .. } in attributes {
+                    result.push_str(&format!(r#" {name}="{value}""#));
                 }
                 if *is_self_closing {
                     result.push_str(" /");
@@ -204,13 +346,19 @@ mod tests {
 
     #[test]
     fn test_token_display_comment() {
-        let token = Token::Comment("Hello World".to_string());
+        let token = Token::Comment {
+            value: "Hello World".to_string(),
+            closing: CommentClosing::Normal,
+        };
         assert_eq!(format!("{token}"), "<!-- Hello World -->");
     }
 
     #[test]
     fn test_token_display_comment_with_html() {
-        let token = Token::Comment("<p>Hello world</p>".to_string());
+        let token = Token::Comment {
+            value: "<p>Hello world</p>".to_string(),
+            closing: CommentClosing::Normal,
+        };
         assert_eq!(format!("{token}"), "<!-- <p>Hello world</p> -->");
     }
 
@@ -225,24 +373,21 @@ mod tests {
         let token = Token::StartTag {
             name: "html".to_string(),
             is_self_closing: false,
-            attributes: HashMap::new(),
+            attributes: vec![],
         };
         assert_eq!(format!("{token}"), "<html>");
 
-        let mut attributes = HashMap::new();
-        attributes.insert("foo".to_string(), "bar".to_string());
-
         let token = Token::StartTag {
             name: "html".to_string(),
             is_self_closing: false,
-            attributes,
+            attributes: vec![Attribute::new("foo", "bar"), Attribute::new("a", "b")],
         };
-        assert_eq!(format!("{token}"), r#"<html foo="bar">"#);
+        assert_eq!(format!("{token}"), r#"<html foo="bar" a="b">"#);
 
         let token = Token::StartTag {
             name: "br".to_string(),
             is_self_closing: true,
-            attributes: HashMap::new(),
+            attributes: vec![],
         };
         assert_eq!(format!("{token}"), "<br />");
     }
@@ -267,7 +412,7 @@ mod tests {
         let token = Token::StartTag {
             name: "div".to_string(),
             is_self_closing: false,
-            attributes: HashMap::new(),
+            attributes: vec![],
         };
         assert!(token.is_start_tag("div"));
         assert!(!token.is_start_tag("span"));
@@ -278,20 +423,77 @@ mod tests {
         let start_tag = Token::StartTag {
             name: "div".to_string(),
             is_self_closing: false,
-            attributes: HashMap::new(),
+            attributes: vec![],
         };
         let other_tag = Token::Text("TestingText".to_string());
         assert!(start_tag.is_any_start_tag());
         assert!(!other_tag.is_any_start_tag());
     }
 
+    #[test]
+    fn test_attr_map() {
+        let token = Token::StartTag {
+            name: "a".to_string(),
+            is_self_closing: false,
+            attributes: vec![
+                Attribute::new("x", "1"),
+                Attribute::new("y", "2"),
+                Attribute::new("x", "3"),
+            ],
+        };
+        let expected = HashMap::from([
+            ("x".to_string(), "1".to_string()),
+            ("y".to_string(), "2".to_string()),
+        ]);
+        assert_eq!(token.attr_map(), expected);
+        assert_eq!(token.attr("x"), Some("1"));
+        assert_eq!(token.attr("z"), None);
+
+        assert!(Token::Text("x=1".to_string()).attr_map().is_empty());
+    }
+
+    #[test]
+    fn test_coalesce_characters() {
+        let tokens = vec![
+            Token::Text("a".to_string()),
+            Token::Text("b".to_string()),
+            Token::Text("c".to_string()),
+        ];
+        let coalesced = tokens.into_iter().coalesce_characters().collect::<Vec<_>>();
+        assert_eq!(coalesced, vec![Token::Text("abc".to_string())]);
+
+        let tokens = vec![
+            Token::Text("a".to_string()),
+            Token::Text("b".to_string()),
+            Token::Comment {
+                value: "c".to_string(),
+                closing: CommentClosing::Normal,
+            },
+            Token::Text("d".to_string()),
+            Token::Eof,
+        ];
+        let coalesced = tokens.into_iter().coalesce_characters().collect::<Vec<_>>();
+        assert_eq!(
+            coalesced,
+            vec![
+                Token::Text("ab".to_string()),
+                Token::Comment {
+                    value: "c".to_string(),
+                    closing: CommentClosing::Normal,
+                },
+                Token::Text("d".to_string()),
+                Token::Eof,
+            ]
+        );
+    }
+
     #[test]
     fn test_is_text_token() {
         let text_token = Token::Text("TestingText".to_string());
         let other_token = Token::StartTag {
             name: "div".to_string(),
             is_self_closing: false,
-            attributes: HashMap::new(),
+            attributes: vec![],
         };
         assert!(text_token.is_text_token());
         assert!(!other_token.is_text_token());
@@ -13,3 +13,24 @@ pub(crate) fn is_valid_id_attribute_value(value: &str) -> bool {
     // but doesn't specify it should *start* with a character
     value.contains(char::is_alphabetic)
 }
+
+/// Returns true when both strings are equal when ASCII letters are compared case-insensitively.
+/// This is the "ASCII case-insensitive" match used for tag names and several attribute values.
+pub(crate) fn ascii_case_insensitive_eq(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_case_insensitive() {
+        assert!(ascii_case_insensitive_eq("SCRIPT", "script"));
+        assert!(ascii_case_insensitive_eq("ScRiPt", "sCrIpT"));
+        assert!(!ascii_case_insensitive_eq("script", "scripts"));
+        // Only ASCII letters are folded
+        assert!(!ascii_case_insensitive_eq("\u{212A}", "k"));
+        assert!(!ascii_case_insensitive_eq("É", "é"));
+    }
+}
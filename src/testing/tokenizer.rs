@@ -5,7 +5,7 @@ use crate::html5::{
     error_logger::ErrorLogger,
     tokenizer::{
         state::State as TokenState,
-        token::Token,
+        token::{Attribute, CommentClosing, Token},
         {Options, Tokenizer},
     },
 };
@@ -17,7 +17,7 @@ use serde::{
     Deserialize, Deserializer,
 };
 use serde_json::Value;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -37,6 +37,7 @@ impl TokenizerBuilder {
             Some(Options {
                 initial_state: self.state,
                 last_start_tag: self.last_start_tag.clone().unwrap_or_default(),
+                ..Default::default()
             }),
             error_logger.clone(),
         )
@@ -98,7 +99,7 @@ where
     let tokens: Vec<Vec<Value>> = Deserialize::deserialize(deserializer)?;
     let mut output = vec![];
 
-    fn attributes(value: &Value) -> HashMap<String, String> {
+    fn attributes(value: &Value) -> Vec<Attribute> {
         value
             .as_object()
             .unwrap()
@@ -107,10 +108,10 @@ where
                 if value.is_null() {
                     None
                 } else {
-                    Some((name.to_owned(), value.as_str().unwrap().to_owned()))
+                    Some(Attribute::new(name, value.as_str().unwrap()))
                 }
             })
-            .collect::<HashMap<String, String>>()
+            .collect()
     }
 
     for values in tokens {
@@ -119,7 +120,10 @@ where
         let token = match values.len() {
             2 => match kind {
                 "Character" => Token::Text(values[1].as_str().unwrap().to_owned()),
-                "Comment" => Token::Comment(values[1].as_str().unwrap().to_owned()),
+                "Comment" => Token::Comment {
+                    value: values[1].as_str().unwrap().to_owned(),
+                    closing: CommentClosing::Normal,
+                },
                 "EndTag" => Token::EndTag {
                     name: values[1].as_str().unwrap().to_owned(),
                     is_self_closing: false,
@@ -217,8 +221,23 @@ impl TestSpec {
 
             // There can be multiple tokens to match. Make sure we match all of them
             for expected in &self.output {
-                let actual = tokenizer.next_token(ParserData::default()).unwrap();
-                assert_eq!(self.escape(&actual), self.escape(expected));
+                let mut actual = tokenizer.next_token(ParserData::default()).unwrap();
+                // The expected output does not record how comments were closed, nor the source
+                // order and raw values of attributes
+                let mut expected = expected.clone();
+                for token in [&mut actual, &mut expected] {
+                    match token {
+                        Token::Comment { closing, .. } => *closing = CommentClosing::Normal,
+                        Token::StartTag { attributes, .. } => {
+                            for attr in attributes.iter_mut() {
+                                attr.raw_value.clone_from(&attr.value);
+                            }
+                            attributes.sort_by(|a, b| a.name.cmp(&b.name));
+                        }
+                        _ => {}
+                    }
+                }
+                assert_eq!(self.escape(&actual), self.escape(&expected));
             }
 
             let borrowed_error_logger = tokenizer.error_logger.borrow();
@@ -281,7 +300,15 @@ impl TestSpec {
         }
 
         match token {
-            Token::Comment(value) => Token::Comment(escape(value)),
+            Token::Comment { value, closing } => Token::Comment {
+                value: escape(value),
+                closing: *closing,
+            },
+
+            Token::ProcessingInstruction { target, data } => Token::ProcessingInstruction {
+                target: escape(target),
+                data: escape(data),
+            },
 
             Token::DocType {
                 name,
@@ -376,7 +403,6 @@ pub fn fixtures() -> impl Iterator<Item = FixtureFile> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
 
     fn parse(i: &str) -> TestSpec {
         serde_json::from_str(i).expect("error parsing")
@@ -398,7 +424,7 @@ mod tests {
             test.output,
             &[Token::StartTag {
                 name: "h".into(),
-                attributes: HashMap::from([("a".into(), "&noti;".into())]),
+                attributes: vec![Attribute::new("a", "&noti;")],
                 is_self_closing: false,
             }],
         );
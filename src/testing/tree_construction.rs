@@ -93,7 +93,10 @@ impl Harness {
 
     /// Run the html5 parser and return the document tree and errors
     fn do_parse(&mut self, scripting_enabled: bool) -> Result<(DocumentHandle, Vec<ParseError>)> {
-        let options = Html5ParserOptions { scripting_enabled };
+        let options = Html5ParserOptions {
+            scripting_enabled,
+            ..Default::default()
+        };
         let mut chars = CharIterator::new();
         chars.read_from_str(self.test.spec_data(), None);
 
@@ -103,9 +106,9 @@ impl Harness {
             self.parse_fragment(fragment, chars, options)?
         } else {
             let document = DocumentBuilder::new_document();
-            let parser_errors =
+            let result =
                 Html5Parser::parse_document(&mut chars, Document::clone(&document), Some(options))?;
-            (document, parser_errors)
+            (document, result.errors)
         };
 
         Ok((document, parse_errors))
@@ -247,3 +250,116 @@ impl Harness {
         result
     }
 }
+
+/// Asserts that the document matches the expected tree dump (in the html5lib tree construction
+/// format, ie: `| <html>`). On mismatch, panics with a line-by-line diff where `-` lines are
+/// expected and `+` lines are actual.
+#[cfg(test)]
+pub(crate) fn assert_tree_eq(actual: &DocumentHandle, expected_dump: &str) {
+    let generator = TreeOutputGenerator::new(Document::clone(actual));
+    let actual = generator.generate().join("\n");
+
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    let expected_lines: Vec<&str> = expected_dump.trim().lines().map(str::trim_end).collect();
+    if actual_lines == expected_lines {
+        return;
+    }
+
+    let mut diff = String::new();
+    for i in 0..actual_lines.len().max(expected_lines.len()) {
+        match (expected_lines.get(i), actual_lines.get(i)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                diff.push_str(&format!("  {expected}\n"));
+            }
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    diff.push_str(&format!("- {expected}\n"));
+                }
+                if let Some(actual) = actual {
+                    diff.push_str(&format!("+ {actual}\n"));
+                }
+            }
+        }
+    }
+
+    panic!("document tree does not match the expected tree:\n{diff}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html5::node::NodeData;
+
+    fn parse(input: &str) -> DocumentHandle {
+        let mut chars = CharIterator::new();
+        chars.read_from_str(input, None);
+
+        let document = DocumentBuilder::new_document();
+        let _ = Html5Parser::parse_document(&mut chars, Document::clone(&document), None);
+        document
+    }
+
+    #[test]
+    fn tree_eq() {
+        let document = parse(r#"<p class="x">a<b>c</b></p>"#);
+        assert_tree_eq(
+            &document,
+            r#"
+| <html>
+|   <head>
+|   <body>
+|     <p>
+|       class="x"
+|       "a"
+|       <b>
+|         "c"
+"#,
+        );
+    }
+
+    #[test]
+    fn template_contents() {
+        // A <td> outside a table is dropped, but inside a template it is kept in the contents
+        let document = parse("<template><td>x</td></template><td>y</td>");
+        assert_tree_eq(
+            &document,
+            r#"
+| <html>
+|   <head>
+|     <template>
+|       content
+|         <td>
+|           "x"
+|   <body>
+|     "y"
+"#,
+        );
+
+        // document -> html -> head -> template
+        let binding = document.get();
+        let template = binding.get_node_by_id(3.into()).unwrap();
+        let NodeData::Element(element) = &template.data else {
+            panic!("expected an element");
+        };
+        assert_eq!(element.name(), "template");
+        assert!(element.template_contents.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "- |       <i>\n+ |       <b>")]
+    fn tree_mismatch() {
+        let document = parse("<p>a<b>c</b></p>");
+        assert_tree_eq(
+            &document,
+            r#"
+| <html>
+|   <head>
+|   <body>
+|     <p>
+|       "a"
+|       <i>
+|         "c"
+"#,
+        );
+    }
+}
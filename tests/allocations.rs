@@ -0,0 +1,71 @@
+//! Checks that the tokenizer reuses its internal buffers, so memory usage stays bounded no
+//! matter how many tokens are generated.
+use gosub_engine::bytes::{CharIterator, Encoding};
+use gosub_engine::html5::error_logger::ErrorLogger;
+use gosub_engine::html5::tokenizer::token::Token;
+use gosub_engine::html5::tokenizer::{ParserData, Tokenizer};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Allocator that keeps track of the live bytes allocated by the current thread
+struct CountingAllocator;
+
+thread_local! {
+    static LIVE_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = LIVE_BYTES.try_with(|b| b.set(b.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE_BYTES.try_with(|b| b.set(b.get().saturating_sub(layout.size())));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn live_bytes() -> usize {
+    LIVE_BYTES.with(Cell::get)
+}
+
+#[test]
+fn tokenizer_memory_is_bounded() {
+    let input = r#"<p class="a" id=b>some text &amp; more</p><!-- comment -->"#.repeat(2000);
+
+    let mut chars = CharIterator::new();
+    chars.read_from_str(&input, Some(Encoding::UTF8));
+
+    let error_logger = Rc::new(RefCell::new(ErrorLogger::new()));
+    let mut tokenizer = Tokenizer::new(&mut chars, None, error_logger);
+
+    let mut count = 0;
+    let mut warmed_up = 0;
+    let mut peak = 0;
+    loop {
+        let token = tokenizer.next_token(ParserData::default()).unwrap();
+        if token == Token::Eof {
+            break;
+        }
+        drop(token);
+        count += 1;
+
+        if count == 100 {
+            warmed_up = live_bytes();
+        } else if count > 100 {
+            peak = peak.max(live_bytes());
+        }
+    }
+
+    assert_eq!(count, 2000 * 4);
+    // After warming up, tokenizing thousands of additional tokens may not keep on allocating
+    assert!(
+        peak <= warmed_up + 1024,
+        "memory grew from {warmed_up} to {peak} bytes"
+    );
+}